@@ -1,6 +1,42 @@
 #!/bin/bash
 
-# Runs inside the docker container and builds the crate's documentation.
+# Runs inside the sandbox and builds the crate's documentation with the
+# toolchain named by RUSTUP_TOOLCHAIN. Any arguments are passed on to
+# `cargo doc` (or `cargo rustdoc`).
 
-# Build the crate's documentation.
-cargo doc -q --color never
+set -e
+
+# Install the toolchain if it isn't already (eg. a pinned version)
+if ! rustup run "${RUSTUP_TOOLCHAIN:-stable}" rustc --version > /dev/null 2>&1; then
+  rustup toolchain install --profile minimal "${RUSTUP_TOOLCHAIN:-stable}" >&2
+fi
+
+# Install the standard library of the target being documented, if any
+if [ -n "$CARGO_BUILD_TARGET" ]; then
+  rustup target add "$CARGO_BUILD_TARGET" >&2
+fi
+
+# Report the exact compiler so it can be recorded with the docs.
+rustc --version
+
+# Stop there if DOC_FORMAT is "version", which only resolves the toolchain
+if [ "$DOC_FORMAT" = "version" ]; then
+  exit 0
+fi
+
+# Count this build's compiler cache hits from zero so they can be reported
+if [ "$RUSTC_WRAPPER" = "sccache" ]; then
+  sccache --zero-stats > /dev/null
+fi
+
+# Build the crate's documentation, or its library's rustdoc JSON if
+# DOC_FORMAT is "json".
+if [ "$DOC_FORMAT" = "json" ]; then
+  cargo rustdoc -q --color never --lib "$@" -- -Z unstable-options --output-format json
+else
+  cargo doc -q --color never "$@"
+fi
+
+if [ "$RUSTC_WRAPPER" = "sccache" ]; then
+  sccache --show-stats
+fi
@@ -1,95 +1,349 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs;
 use std::io::{self, Write};
-use std::process::Command;
+use std::path::Path;
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
+use std::time::Duration;
 
-use super::db::{Db, FailedModel};
-use super::store::StoredCrate;
+use super::cache::{Cache, CacheDirs};
+use super::config::CrateConfig;
+use super::db::{BuildModel, Db, FailedModel, TaskModel};
+use super::fallback::Fallback;
+use super::package::{DocsMetadata, Package};
+use super::queue::Job;
+use super::quota::Quota;
+use super::registry::{Registries, Registry};
+use super::sandbox::{Limits, Sandbox};
+use super::store::{Manifest, Store, StoredCrate, Writing, JSON_FILE};
 use super::tasks::*;
 use super::temp_crate::TempCrate;
-use super::util::run_command;
+use super::util::{crate_key, now};
+
+/// Longest time in seconds that a retry waits for, however many attempts
+/// came before it
+const MAX_RETRY_DELAY: u64 = 60 * 60;
 
 #[derive(Clone)]
 pub enum Status {
     Pending,
     Running,
-    /// Succeeded with path to a doc tarball
+    /// Succeeded with the key of the archive of the docs in the store
     Succeeded(String),
-    // Failed with a string describing what went wrong
-    Failed(TaskError),
+    // Failed in a stage with an error describing what went wrong
+    Failed(Stage, TaskError),
+}
+
+/// Settings for the pool of build workers
+#[derive(Clone)]
+pub struct Options {
+    /// Number of builds that may run at the same time
+    pub workers: usize,
+    /// Number of times a build that failed for a transient reason is retried
+    pub retries: u32,
+    /// Seconds to wait before the first retry; doubled for each one after
+    pub retry_backoff: u64,
+    /// Sandbox that `cargo doc` is run in
+    pub sandbox: Arc<Sandbox>,
+    /// Resources each build may use
+    pub limits: Limits,
+    /// Toolchain that crates are built with unless they need another
+    pub toolchain: String,
+    /// Targets documented for crates that don't declare their own (the
+    /// host only if empty)
+    pub targets: Vec<String>,
+    /// Nightly toolchain that rustdoc JSON is built with, if it's enabled
+    pub json_toolchain: Option<String>,
+    /// Local registry that dependencies are vendored from before building
+    /// offline, if any
+    pub mirror: Option<String>,
+    /// Compilation cache shared by builds, if any
+    pub cache: Option<Arc<Cache>>,
+    /// Other ways of building docs tried in turn when a build fails
+    pub fallbacks: Vec<Fallback>,
+    /// Settings for individual crates, keyed by `util::crate_key`
+    pub crates: BTreeMap<String, CrateConfig>,
+    /// Limit on the space the store takes up, enforced periodically while
+    /// serving (see `Quota::start`), if any
+    pub quota: Option<Arc<Quota>>,
+}
+
+impl Options {
+    fn crate_config(&self, job: &Job) -> Option<&CrateConfig> {
+        self.crates.get(&crate_key(&job.registry, &job.name))
+    }
+
+    /// Limits for a job, taking into account any set for its crate
+    pub fn limits_for(&self, job: &Job) -> Limits {
+        match self.crate_config(job).and_then(|krate| krate.limits.as_ref()) {
+            Some(limits) => self.limits.merge(limits),
+            None => self.limits.clone(),
+        }
+    }
+
+    /// Seconds that a build may take, going by the longest timeout of any
+    /// crate: a command to fetch or vendor dependencies, then one for the
+    /// docs of the default target and each configured one, each of the
+    /// fallbacks, and the rustdoc JSON, each run until it times out. `None`
+    /// if any builds have no timeout.
+    pub fn longest_build(&self) -> Option<u64> {
+        let mut timeout = match self.limits.timeout {
+            Some(timeout) => timeout,
+            None => return None,
+        };
+
+        for krate in self.crates.values() {
+            match krate.limits.as_ref().map(|limits| self.limits.merge(limits).timeout) {
+                Some(Some(crate_timeout)) => timeout = cmp::max(timeout, crate_timeout),
+                Some(None) => return None,
+                None => (),
+            }
+        }
+
+        let commands = 3 + self.targets.len() + self.fallbacks.len();
+
+        Some(timeout.saturating_mul(commands as u64))
+    }
+
+    /// Seconds that what a build may still be using is kept for:
+    /// `longest_build`, or a day if builds have no timeout.
+    pub fn grace(&self) -> u64 {
+        self.longest_build().unwrap_or(60 * 60 * 24)
+    }
+
+    /// Toolchain to build a job with: the one configured for its crate if
+    /// any, otherwise the default unless it's older than the crate's
+    /// `rust-version`. A channel such as "stable" is compared by the version
+    /// it resolves to in the sandbox, which `resolve` prints (see
+    /// `DocTask::run_version`). Fails if it can't be resolved.
+    pub fn toolchain_for<F>(&self, job: &Job, package: &Package, resolve: F) -> Result<String, TaskError>
+        where F: FnOnce() -> Result<TaskOutput, TaskError> {
+        if let Some(toolchain) = self.crate_config(job).and_then(|krate| krate.toolchain.clone()) {
+            return Ok(toolchain)
+        }
+
+        let rust_version = match package.rust_version {
+            Some(ref rust_version) => rust_version,
+            None => return Ok(self.toolchain.clone()),
+        };
+
+        let version = try!(toolchain_version(&self.toolchain, resolve));
+        if is_older_version(&version, rust_version) {
+            Ok(rust_version.clone())
+        } else {
+            Ok(self.toolchain.clone())
+        }
+    }
 }
 
 /// Handles compiling a crate's documentation.
 pub struct Builder {
     pub temp_crate: TempCrate,
     pub status: RwLock<Status>,
-    /// Destination path where the tarball will end up
+    /// Where the docs are built before they're published
     pub dest: StoredCrate,
+    /// Toolchain chosen for the build, once the crate has been expanded
+    pub toolchain: Option<String>,
+    /// Compiler cache hits and misses of the docs build, if it used sccache
+    pub cache_stats: Option<(u64, u64)>,
+    /// Record of the build, available once it has finished running
+    pub build: Option<BuildModel>,
+    /// Held from when the build starts storing its docs until they're
+    /// published, so that eviction doesn't remove blobs they use
+    pub writing: Option<Writing>,
 }
 
 impl Builder {
     // dest_dir: Destination directory in which the tarball should be placed
     //           after downloading
-    pub fn new(name: &str, version: &str, dest: StoredCrate) -> Builder {
+    pub fn new(registry: &str, name: &str, version: &str, dest: StoredCrate) -> Builder {
         Builder {
-            temp_crate: TempCrate::new(name, version),
+            temp_crate: TempCrate::new(registry, name, version),
             status: RwLock::new(Status::Pending),
             dest: dest.clone(),
+            toolchain: None,
+            cache_stats: None,
+            build: None,
+            writing: None,
         }
     }
 
-    // Spawn a new thread to download, compile, and store the crate's docs.
-    // The builder must be wrapped in an `RwLock`. The thread will acquire
-    // a write lock on it, but other threads can still read it to inspect
-    // its status.
-    pub fn spawn(db: Arc<Mutex<Db>>, builder: Arc<RwLock<Builder>>) {
+    /// Starts `options.workers` worker threads that take jobs off the build
+    /// queue and build them one at a time, so that at most that many builds
+    /// are ever running at once.
+    pub fn start_workers(db: Arc<Mutex<Db>>, store: Arc<Store>, registries: Arc<Registries>, options: Options) {
+        if let Err(err) = Db::start_lease(&db) {
+            let _ = write!(io::stderr(), "Unable to take a lease on builds: {}\n", err.0);
+        }
+
+        {
+            let readable_db = db.lock().unwrap();
+            let result = readable_db.requeue_interrupted_builds().and_then(|_| readable_db.reconcile_queued());
+            if let Err(err) = result {
+                let _ = write!(io::stderr(), "Unable to requeue interrupted builds: {}\n", err.0);
+            }
+        }
+
+        for _ in 0..options.workers {
+            let db = db.clone();
+            let store = store.clone();
+            let registries = registries.clone();
+            let options = options.clone();
+
+            thread::spawn(move || {
+                loop {
+                    let job = { db.lock().unwrap().dequeue_build() };
+
+                    // Keep the worker going through Redis errors; the job
+                    // stays on the processing list to be requeued
+                    match job {
+                        Ok(Some(job)) => Builder::work(&db, &store, &registries, &options, job),
+                        Ok(None) => thread::sleep(Duration::from_secs(1)),
+                        Err(err) => {
+                            let _ = write!(io::stderr(), "Unable to take a job off the queue: {}\n", err.0);
+                            thread::sleep(Duration::from_secs(1));
+                        },
+                    }
+                }
+            });
+        }
+    }
+
+    /// Downloads, compiles, and stores the docs for a job on the current
+    /// thread. The builder is wrapped in an `RwLock` and added to the builds
+    /// in progress so that other threads can inspect its status.
+    pub fn work(db: &Arc<Mutex<Db>>, store: &Store, registries: &Registries, options: &Options, job: Job) {
+        let registry = match registries.get(&job.registry) {
+            Some(registry) => registry,
+            None => {
+                // The registry may have been removed from the configuration
+                // since the job was queued
+                let _ = write!(io::stderr(), "Skipping {}: unknown registry\n", job.pair());
+                Builder::finish(db, &job);
+                return
+            },
+        };
+
+        let krate = store.for_job(&job);
+
+        // It may have been built by an earlier job for the same version
+        if store.contains(&krate) && !job.rebuild {
+            Builder::finish(db, &job);
+            return
+        }
+
+        // Docs are built in a staging area and only published once they're
+        // complete, so nobody sees a partial build and a rebuild's docs
+        // don't replace the current ones until they're ready
+        let dest = store.make_staging(&krate);
+        let builder = Arc::new(RwLock::new(Builder::new(&job.registry, &job.name, &job.version, dest.clone())));
+
+        // Rebuilt docs are still served while they're rebuilt, so they don't
+        // count as being built
+        let in_progress = if job.rebuild { dest.clone() } else { krate.clone() };
         {
             let mut writeable_db = db.lock().unwrap();
-            writeable_db.add_build_in_progress(builder.clone());
+            writeable_db.add_build_in_progress(in_progress.clone(), builder.clone());
         }
 
-        let builder = builder.clone();
+        let mut attempts = 0;
+        let mut status;
+
+        // Retry transient failures with exponential backoff
+        loop {
+            attempts += 1;
+            db.lock().unwrap().set_attempts(&job.pair(), attempts);
 
-        thread::spawn(move || {
-            let status = {
+            status = {
                 let mut writeable_builder = builder.write().unwrap();
-                writeable_builder.run()
+                writeable_builder.run(registry, options, &job)
             };
 
-            let mut writeable_db = db.lock().unwrap();
+            if let Some(ref build) = builder.read().unwrap().build {
+                let _ = db.lock().unwrap().add_build(&job.pair(), build);
+            }
 
-            if let Status::Failed(err) = status {
-                let code: i32;
-                let message: String;
-
-                match err {
-                    TaskError::Command(status, stdout, stderr) => {
-                        code = status.code().unwrap_or(-1);
-                        message = format!("{}\n{}", stdout, stderr).trim().to_owned();
-                    },
-                    _ => {
-                        code = -1;
-                        message = "Unknown reason".to_owned();
-                    }
-                }
+            let retry = match status {
+                Status::Failed(_, ref err) => err.is_transient() && attempts <= options.retries,
+                _ => false,
+            };
 
-                // Model representing the error code and message
-                let failed = FailedModel {
-                    code: code,
-                    message: message,
-                };
-                // Name-version pair
-                let pair = {
-                    let readable_builder = builder.read().unwrap();
-                    readable_builder.temp_crate.pair()
-                };
-
-                let _ = writeable_db.set_failed(&pair, failed);
+            if !retry {
+                break
             }
 
-            // Remove the builder from the list in-progrss builds
-            writeable_db.remove_build_in_progress(builder.clone());
-        });
+            // Nothing is published before the retry
+            builder.write().unwrap().writing = None;
+
+            let delay = retry_delay(options.retry_backoff, attempts);
+            let _ = write!(io::stderr(), "Retrying {} in {} seconds (attempt {})\n", job.pair(), delay, attempts + 1);
+            thread::sleep(Duration::from_secs(delay));
+        }
+
+        // Publishing takes several requests to the store, so it's done
+        // without holding the database. Until the build is removed from the
+        // builds in progress below, its docs count as built.
+        status = match status {
+            Status::Succeeded(_) => {
+                match store.publish(&dest, &krate) {
+                    Ok(()) => Status::Succeeded(krate.archive_key()),
+                    Err(err) => Status::Failed(Stage::Store, TaskError::Io(format!("Unable to publish docs: {}", err))),
+                }
+            },
+            status => status,
+        };
+
+        // Clean up the staging area, which is already empty if the docs were
+        // published. Any current docs are left alone if the build failed.
+        let _ = store.remove(&dest);
+        builder.write().unwrap().writing = None;
+
+        let published = match status {
+            Status::Succeeded(_) => true,
+            _ => false,
+        };
+
+        let mut writeable_db = db.lock().unwrap();
+
+        // Count the build as an access so that it isn't the first thing
+        // evicted
+        if published {
+            writeable_db.record_access(&job.pair());
+        }
+
+        // A failed rebuild only shows up in the build records, since the
+        // docs it would have replaced are still served
+        if let (Status::Failed(stage, err), false) = (status, job.rebuild) {
+            // Model representing where and why the build failed
+            let failed = FailedModel {
+                code: err.code(),
+                message: err.message(),
+                stage: stage.as_str().to_owned(),
+                category: err.category().as_str().to_owned(),
+                attempts: attempts,
+            };
+
+            let _ = writeable_db.set_failed(&job, failed);
+        }
+
+        // Remove the builder from the list in-progress builds
+        writeable_db.remove_build_in_progress(&in_progress);
+        if let Err(err) = writeable_db.finish_build(&job) {
+            let _ = write!(io::stderr(), "Unable to finish the build of {}: {}\n", job.pair(), err.0);
+        }
+        drop(writeable_db);
+
+        if let Some(ref cache) = options.cache {
+            cache.trim();
+        }
+    }
+
+    // Marks a job as finished without building it.
+    fn finish(db: &Mutex<Db>, job: &Job) {
+        if let Err(err) = db.lock().unwrap().finish_build(job) {
+            let _ = write!(io::stderr(), "Unable to finish the build of {}: {}\n", job.pair(), err.0);
+        }
     }
 
     fn update_status(&self, new_status: Status) -> Status {
@@ -98,45 +352,393 @@ impl Builder {
         new_status
     }
 
-    fn run(&mut self) -> Status {
-        let temp_crate = &self.temp_crate;
+    fn run(&mut self, registry: &Registry, options: &Options, job: &Job) -> Status {
         self.update_status(Status::Running);
 
-        let download = DownloadTask::new(temp_crate);
-        let expand   = ExpandTask::new(temp_crate);
-        let doc      = DocTask::new(temp_crate);
-
-        let result = download.run()
-            .and_then(|_| expand.run())
-            .and_then(|_| doc.run())
-            .and_then(|doc_path| {
-                let dest_path = self.dest.path();
-
-                let mkdirp = move || {
-                    Command::new("mkdir")
-                            .arg("-p").arg(dest_path)
-                            .output()
-                };
-                let mv = move || {
-                    Command::new("cp")
-                            .arg("-r")
-                            .arg(format!("{}/", doc_path))
-                            .arg(dest_path)
-                            .output()
-                };
-
-                run_command(mkdirp)
-                    .and_then(|_| run_command(mv))
-            });
+        let started_at = now();
+        let mut tasks = vec![];
+
+        let result = self.run_tasks(registry, options, job, &mut tasks);
+        let manifest = result.as_ref().ok().cloned();
 
         self.temp_crate.cleanup().unwrap(); // Always cleanup!
 
-        if let Err(err) = result {
-            let _ = write!(io::stderr(), "Error building documentation: {:?}\n", err);
-            self.update_status(Status::Failed(err))
+        let finished_at = now();
+        self.build = Some(BuildModel {
+            started_at: started_at,
+            finished_at: finished_at,
+            duration: finished_at - started_at,
+            succeeded: result.is_ok(),
+            toolchain: describe_toolchain(&*options.sandbox, self.toolchain.as_ref()),
+            rustc: manifest.and_then(|manifest| manifest.rustc),
+            cache_hits: self.cache_stats.map(|(hits, _)| hits),
+            cache_misses: self.cache_stats.map(|(_, misses)| misses),
+            tasks: tasks,
+        });
+
+        if let Err((stage, err)) = result {
+            let _ = write!(io::stderr(), "Error building documentation in {} stage: {:?}\n", stage.as_str(), err);
+            self.update_status(Status::Failed(stage, err))
         } else {
-            let dest_path = self.dest.0.clone();
-            self.update_status(Status::Succeeded(dest_path))
+            let archive_key = self.dest.archive_key();
+            self.update_status(Status::Succeeded(archive_key))
         }
     }
+
+    // Runs each task in turn, stopping at the first failure. Every task that
+    // runs is recorded in `tasks`. Returns the manifest of the stored docs.
+    fn run_tasks(&mut self, registry: &Registry, options: &Options, job: &Job, tasks: &mut Vec<TaskModel>) -> Result<Manifest, (Stage, TaskError)> {
+        let mut limits = options.limits_for(job);
+
+        try!(record(tasks, Stage::Download, DownloadTask::new(&self.temp_crate, registry).run()));
+        let checksum = try!(record(tasks, Stage::Checksum, ChecksumTask::new(&self.temp_crate, registry).run()));
+        try!(record(tasks, Stage::Expand, ExpandTask::new(&self.temp_crate).run()));
+
+        let package = try!(Package::read(&self.temp_crate.path).map_err(|err| (Stage::Expand, err)));
+        let toolchain = try! {
+            options.toolchain_for(job, &package, || {
+                let name = format!("{} version", Stage::Doc.as_str());
+                let result = DocTask::new(&self.temp_crate, &*options.sandbox, &options.toolchain, None, &package.docs, &limits, None).run_version();
+
+                record_as(tasks, Stage::Doc, name, result).map_err(|(_, err)| err)
+            }).map_err(|err| (Stage::Doc, err))
+        };
+        self.toolchain = Some(toolchain.clone());
+
+        // With a mirror every dependency is vendored up front, so the
+        // sandbox doesn't need the network
+        if let Some(ref mirror) = options.mirror {
+            try!(record(tasks, Stage::Vendor, VendorTask::new(&self.temp_crate, mirror, &toolchain, &limits).run()));
+            limits.network = Some(false);
+        }
+
+        let metadata = &match job.variant {
+            Some(ref variant) => variant.apply(&package.docs),
+            None => package.docs.clone(),
+        };
+        let (default_target, mut other_targets) = metadata.targets(&options.targets);
+        let default_target = default_target.as_ref().map(|target| &target[..]);
+
+        // Variants are only built for the default target
+        if job.variant.is_some() {
+            other_targets.clear();
+        }
+
+        // Builds share the cache while they run, so it can't be trimmed
+        // from under them
+        let _cache_guard = options.cache.as_ref().map(|cache| cache.read());
+
+        // The default target has to build, but the others are best-effort.
+        // They're built the same way as the default target was in the end.
+        let (doc, fallback, metadata, toolchain) = try!(self.doc_with_fallbacks(options, job, tasks, metadata, &toolchain, default_target, &limits));
+        let metadata = &metadata;
+        self.toolchain = Some(toolchain.clone());
+        self.cache_stats = cache_stats(&doc);
+        let dirs = try!(self.cache_dirs(options, job, &toolchain, &limits, tasks));
+
+        // Find which of the crate's own docs were generated, among those of
+        // its dependencies
+        let doc_path = self.temp_crate.doc_path(default_target);
+        let documented = |name: &String| Path::new(&doc_path).join(name).join("index.html").is_file();
+        let lib = if package.has_lib && documented(&package.crate_name()) { Some(package.crate_name()) } else { None };
+        let bins: Vec<String> = package.bin_crate_names().into_iter()
+                                       .filter(|bin| Some(bin) != lib.as_ref() && documented(bin))
+                                       .collect();
+
+        let mut targets = vec![];
+        for target in other_targets {
+            let name = format!("{} {}", Stage::Doc.as_str(), target);
+            let result = DocTask::new(&self.temp_crate, &*options.sandbox, &toolchain, Some(&target[..]), metadata, &limits, dirs.as_ref()).run();
+
+            if record_as(tasks, Stage::Doc, name, result).is_ok() {
+                targets.push(target);
+            }
+        }
+
+        // rustdoc's JSON output is unstable, so it's best-effort too
+        let mut json_path = None;
+        if let (Some(json_toolchain), None) = (options.json_toolchain.as_ref(), job.variant.as_ref()) {
+            // Use the build's own toolchain if it's already a nightly
+            let json_toolchain = if toolchain.starts_with("nightly") { &toolchain } else { json_toolchain };
+            let name = format!("{} json", Stage::Doc.as_str());
+
+            if let Ok(json_dirs) = self.cache_dirs(options, job, json_toolchain, &limits, tasks) {
+                let result = DocTask::new(&self.temp_crate, &*options.sandbox, json_toolchain, default_target, metadata, &limits, json_dirs.as_ref()).run_json();
+
+                if record_as(tasks, Stage::Doc, name, result).is_ok() {
+                    json_path = Some(self.temp_crate.json_path(default_target, &package.crate_name()));
+                }
+            }
+        }
+
+        let manifest = Manifest {
+            checksum: Some(checksum.stdout),
+            toolchain: Some(toolchain),
+            rustc: doc.stdout.lines().find(|line| line.starts_with("rustc ")).map(|line| line.to_owned()),
+            default_target: default_target.map(|target| target.to_owned()),
+            targets: Some(targets),
+            rustdoc_json: Some(json_path.is_some()),
+            image: Some(options.sandbox.describe()),
+            lib: lib,
+            bins: Some(bins),
+            fallback: fallback.map(|fallback| fallback.name()),
+        };
+        self.writing = Some(self.dest.start_writing());
+        try!(record(tasks, Stage::Store, self.store_docs(json_path, &manifest)));
+
+        Ok(manifest)
+    }
+
+    // Builds the docs for the default target as the crate's metadata asks,
+    // then each of the fallbacks that applies in turn until one succeeds.
+    // Returns the build's output, the fallback that succeeded (if any) and
+    // the metadata and toolchain it built with.
+    fn doc_with_fallbacks(&self, options: &Options, job: &Job, tasks: &mut Vec<TaskModel>, metadata: &DocsMetadata, toolchain: &str, target: Option<&str>, limits: &Limits)
+        -> Result<(TaskOutput, Option<Fallback>, DocsMetadata, String), (Stage, TaskError)> {
+        let dirs = try!(self.cache_dirs(options, job, toolchain, limits, tasks));
+        let result = DocTask::new(&self.temp_crate, &*options.sandbox, toolchain, target, metadata, limits, dirs.as_ref()).run();
+
+        let error = match record(tasks, Stage::Doc, result) {
+            Ok(output) => return Ok((output, None, metadata.clone(), toolchain.to_owned())),
+            Err(error) => error,
+        };
+
+        // Only failures to compile are worth retrying differently, and
+        // variants ask for a particular build so they don't fall back
+        let category = error.1.category();
+        if job.variant.is_some() || (category != Category::Compile && category != Category::MissingLibrary) {
+            return Err(error)
+        }
+
+        let output = error.1.message();
+        for fallback in &options.fallbacks {
+            if !fallback.applies(toolchain, &output) {
+                continue
+            }
+
+            // Docs left by the failed build mustn't be published with the
+            // fallback's
+            try!(self.remove_docs(target));
+
+            let (fallback_metadata, fallback_toolchain) = fallback.apply(metadata, toolchain);
+            let dirs = match self.cache_dirs(options, job, &fallback_toolchain, limits, tasks) {
+                Ok(dirs) => dirs,
+                Err(_) => continue,
+            };
+            let name = format!("{} ({})", Stage::Doc.as_str(), fallback.name());
+            let result = DocTask::new(&self.temp_crate, &*options.sandbox, &fallback_toolchain, target, &fallback_metadata, limits, dirs.as_ref()).run();
+
+            if let Ok(output) = record_as(tasks, Stage::Doc, name, result) {
+                return Ok((output, Some(fallback.clone()), fallback_metadata, fallback_toolchain))
+            }
+        }
+
+        // Report the failure of the build the crate's metadata asked for
+        Err(error)
+    }
+
+    // Removes any docs generated for a target.
+    fn remove_docs(&self, target: Option<&str>) -> Result<(), (Stage, TaskError)> {
+        match fs::remove_dir_all(self.temp_crate.doc_path(target)) {
+            Err(ref err) if err.kind() != io::ErrorKind::NotFound => {
+                Err((Stage::Doc, TaskError::Io(format!("Unable to remove docs: {}", err))))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // Cache directories for building with a toolchain, if there's a cache.
+    // The crate's dependencies are first fetched into the toolchain's
+    // registry cache on the host, once per build, unless they were vendored.
+    fn cache_dirs(&self, options: &Options, job: &Job, toolchain: &str, limits: &Limits, tasks: &mut Vec<TaskModel>) -> Result<Option<CacheDirs>, (Stage, TaskError)> {
+        let cache = match options.cache {
+            Some(ref cache) => cache,
+            None => return Ok(None),
+        };
+
+        let dirs = try! {
+            cache.dirs(toolchain, &job.registry, &job.name)
+                .map_err(|err| (Stage::Fetch, TaskError::Io(format!("Unable to create cache directories: {}", err))))
+        };
+
+        let name = format!("{} {}", Stage::Fetch.as_str(), toolchain);
+        let fetched = tasks.iter().any(|task| task.name == name && task.succeeded);
+        if options.mirror.is_none() && !fetched {
+            try!(record_as(tasks, Stage::Fetch, name, FetchTask::new(&self.temp_crate, &dirs, toolchain, limits).run()));
+        }
+
+        Ok(Some(dirs))
+    }
+
+    // Archives the docs generated for each target into the store along with
+    // their manifest. The default target's docs go in the root of the
+    // archive and the others in directories named after them. The rustdoc
+    // JSON, if any, goes in the root as `store::JSON_FILE`.
+    fn store_docs(&self, json_path: Option<String>, manifest: &Manifest) -> Result<TaskOutput, TaskError> {
+        let default_target = manifest.default_target.as_ref().map(|target| &target[..]);
+
+        let archive = || -> io::Result<()> {
+            let mut writer = self.dest.archive_writer();
+
+            try!(writer.add_dir(Path::new(&self.temp_crate.doc_path(default_target)), ""));
+            for target in manifest.targets.as_ref().unwrap() {
+                try!(writer.add_dir(Path::new(&self.temp_crate.doc_path(Some(&target[..]))), target));
+            }
+            if let Some(ref json_path) = json_path {
+                try!(writer.add_file(Path::new(json_path), JSON_FILE));
+            }
+
+            writer.finish()
+        };
+
+        try! {
+            archive().map_err(|err| TaskError::Io(format!("Unable to archive docs: {}", err)))
+        };
+
+        self.dest.write_manifest(manifest)
+            .map(|_| TaskOutput::new(String::new()))
+            .map_err(|err| TaskError::Io(format!("Unable to write manifest: {}", err)))
+    }
+}
+
+
+// Describes the toolchain and sandbox a build ran with for its record (eg.
+// "nightly on docker doc_server:build").
+fn describe_toolchain(sandbox: &Sandbox, toolchain: Option<&String>) -> String {
+    match toolchain {
+        Some(toolchain) => format!("{} on {}", toolchain, sandbox.describe()),
+        None => sandbox.describe(),
+    }
+}
+
+// The version of rustc (eg. "1.75.0") that a toolchain is, or that a channel
+// such as "stable" resolves to going by the output of `resolve`.
+fn toolchain_version<F>(toolchain: &str, resolve: F) -> Result<String, TaskError>
+    where F: FnOnce() -> Result<TaskOutput, TaskError> {
+    if parse_version(toolchain).is_some() {
+        return Ok(toolchain.to_owned())
+    }
+
+    let output = try!(resolve());
+    match output.stdout.lines().filter_map(rustc_version).next() {
+        Some(version) => Ok(version),
+        None => Err(TaskError::CommandExecute(format!("Unable to find which version the {} toolchain is in:\n{}", toolchain, output.stdout))),
+    }
+}
+
+// The version in a line of `rustc --version`'s output, which is of the form
+// "rustc 1.77.0-nightly (5518eaa94 2024-01-29)".
+fn rustc_version(line: &str) -> Option<String> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("rustc") {
+        return None
+    }
+
+    words.next()
+         .and_then(|version| version.split('-').next())
+         .and_then(|version| parse_version(version).map(|_| version.to_owned()))
+}
+
+// Whether `toolchain` is a version (eg. "1.50.0") older than `version`.
+// Missing parts count as zero, so "1.56.0" isn't older than "1.56". Anything
+// that isn't a version is never older.
+fn is_older_version(toolchain: &str, version: &str) -> bool {
+    match (parse_version(toolchain), parse_version(version)) {
+        (Some(mut toolchain), Some(mut version)) => {
+            let len = cmp::max(toolchain.len(), version.len());
+            toolchain.resize(len, 0);
+            version.resize(len, 0);
+
+            toolchain < version
+        },
+        _ => false,
+    }
+}
+
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
+
+// Records the result of a task, tagging its error with the stage it ran in.
+fn record(tasks: &mut Vec<TaskModel>, stage: Stage, result: Result<TaskOutput, TaskError>) -> Result<TaskOutput, (Stage, TaskError)> {
+    record_as(tasks, stage, stage.as_str().to_owned(), result)
+}
+
+// Records the result of a task under a name other than its stage's (eg. to
+// tell apart the doc builds of each target).
+fn record_as(tasks: &mut Vec<TaskModel>, stage: Stage, name: String, result: Result<TaskOutput, TaskError>) -> Result<TaskOutput, (Stage, TaskError)> {
+    let task = match result {
+        Ok(ref output) => {
+            TaskModel {
+                name: name.clone(),
+                succeeded: true,
+                code: 0,
+                stdout: output.stdout.clone(),
+                stderr: output.stderr.clone(),
+            }
+        },
+        Err(TaskError::Command(ref status, ref stdout, ref stderr)) => {
+            TaskModel {
+                name: name.clone(),
+                succeeded: false,
+                code: status.code().unwrap_or(-1),
+                stdout: stdout.clone(),
+                stderr: stderr.clone(),
+            }
+        },
+        Err(ref err) => {
+            TaskModel {
+                name: name.clone(),
+                succeeded: false,
+                code: err.code(),
+                stdout: String::new(),
+                stderr: err.message(),
+            }
+        },
+    };
+
+    tasks.push(task);
+    result.map_err(|err| (stage, err))
+}
+
+// Seconds to wait before retrying after a failed attempt: `backoff`, doubled
+// for each attempt after the first, up to `MAX_RETRY_DELAY`.
+fn retry_delay(backoff: u64, attempts: u32) -> u64 {
+    let factor = if attempts > 63 { u64::max_value() } else { 1 << (attempts - 1) };
+
+    cmp::min(backoff.saturating_mul(factor), MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_older_version, retry_delay, rustc_version, toolchain_version, MAX_RETRY_DELAY};
+
+    #[test]
+    fn compares_versions() {
+        assert!(is_older_version("1.50.0", "1.56"));
+        assert!(is_older_version("1.9.0", "1.10.0"));
+        assert!(!is_older_version("1.56.0", "1.56"));
+        assert!(!is_older_version("1.70.0", "1.56.1"));
+        assert!(!is_older_version("stable", "1.56"));
+    }
+
+    #[test]
+    fn pinned_toolchain_is_its_own_version() {
+        let version = toolchain_version("1.56.0", || panic!("resolved a pinned toolchain"));
+        assert_eq!(version.ok(), Some("1.56.0".to_owned()));
+    }
+
+    #[test]
+    fn parses_rustc_version() {
+        assert_eq!(rustc_version("rustc 1.77.0-nightly (5518eaa94 2024-01-29)"), Some("1.77.0".to_owned()));
+        assert_eq!(rustc_version("rustc 1.75.0 (82e1608df 2023-12-21)"), Some("1.75.0".to_owned()));
+        assert_eq!(rustc_version("info: installing component 'rustc'"), None);
+    }
+
+    #[test]
+    fn caps_retry_delay() {
+        assert_eq!(retry_delay(5, 1), 5);
+        assert_eq!(retry_delay(5, 3), 20);
+        assert_eq!(retry_delay(5, 100), MAX_RETRY_DELAY);
+    }
 }
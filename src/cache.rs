@@ -0,0 +1,250 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard};
+use std::time::SystemTime;
+use libc;
+
+/// Compilation caches shared by every build, kept separately for each
+/// toolchain. Each toolchain gets a cargo registry cache (so dependencies
+/// are downloaded and extracted once) and, optionally, an sccache directory
+/// for each crate (so its dependencies are compiled once).
+///
+/// Sandboxes run crates' build scripts, so they only get the registry cache
+/// read-only; it's filled on the host by `tasks::FetchTask`. sccache has to
+/// write to its directory, so each crate gets its own, and a crate can only
+/// affect the compiler output of its own builds.
+///
+/// Cargo and sccache lock their own caches, so concurrent builds can share
+/// them. Builds hold a shared lock on the cache while they run, and trimming
+/// an exclusive one, so it's only trimmed in between them. The lock is a
+/// file lock as well, covering builds run by other processes (eg. the
+/// `build` command next to the server).
+pub struct Cache {
+    root: PathBuf,
+    /// Megabytes each registry cache may take up before it's trimmed
+    registry_size: u64,
+    /// Megabytes each toolchain's sccache directories may take up in total,
+    /// if sccache is used
+    sccache_size: Option<u64>,
+    lock: RwLock<()>,
+}
+
+/// Cache directories for a build
+#[derive(Clone, Debug)]
+pub struct CacheDirs {
+    /// `CARGO_HOME` of the host's steps that fill the registry cache
+    pub cargo_home: PathBuf,
+    /// Replaces `$CARGO_HOME/registry`, read-only
+    pub registry: PathBuf,
+    /// The crate's sccache directory
+    pub sccache: Option<PathBuf>,
+    /// Megabytes that sccache is told it may use
+    pub sccache_size: u64,
+}
+
+/// Shares the cache with other builds until it's dropped
+pub struct CacheGuard<'a> {
+    _lock: RwLockReadGuard<'a, ()>,
+    _file: Option<File>,
+}
+
+impl Cache {
+    pub fn new(root: PathBuf, registry_size: u64, sccache_size: Option<u64>) -> Cache {
+        Cache {
+            root: root,
+            registry_size: registry_size,
+            sccache_size: sccache_size,
+            lock: RwLock::new(()),
+        }
+    }
+
+    /// Returns (creating them if needed) the cache directories for builds
+    /// of a crate with a toolchain.
+    pub fn dirs(&self, toolchain: &str, registry_name: &str, name: &str) -> io::Result<CacheDirs> {
+        let dir = self.root.join(toolchain);
+        let registry = dir.join("registry");
+        try!(fs::create_dir_all(&registry));
+
+        let sccache = match self.sccache_size {
+            Some(_) => {
+                let sccache = dir.join("sccache").join(registry_name).join(name);
+                try!(fs::create_dir_all(&sccache));
+                Some(sccache)
+            },
+            None => None,
+        };
+
+        Ok(CacheDirs {
+            cargo_home: dir,
+            registry: registry,
+            sccache: sccache,
+            sccache_size: self.sccache_size.unwrap_or(0),
+        })
+    }
+
+    /// Shares the cache with other builds until the guard is dropped.
+    pub fn read(&self) -> CacheGuard {
+        let lock = self.lock.read().unwrap();
+
+        CacheGuard {
+            _lock: lock,
+            _file: self.lock_file(libc::LOCK_SH),
+        }
+    }
+
+    /// Shrinks any registry cache that's over its size limit, and each
+    /// toolchain's sccache directories if they're over theirs, waiting for
+    /// running builds to finish first. Extracted sources are removed first
+    /// since cargo can extract them again without downloading anything, and
+    /// then the least recently modified `.crate` archives. The sccache
+    /// directories of the crates built least recently are removed; sccache
+    /// trims each one itself too.
+    pub fn trim(&self) {
+        let limit = self.registry_size * 1024 * 1024;
+        let sccache_limit = self.sccache_size.map(|size| size * 1024 * 1024);
+
+        let toolchains = subdirs(&self.root);
+        let over = |dir: &PathBuf| {
+            dir_size(&dir.join("registry")) > limit ||
+            sccache_limit.map_or(false, |sccache_limit| dir_size(&dir.join("sccache")) > sccache_limit)
+        };
+        if !toolchains.iter().any(&over) {
+            return
+        }
+
+        let _guard = self.lock.write().unwrap();
+        let _file = self.lock_file(libc::LOCK_EX);
+
+        for dir in toolchains {
+            let registry = dir.join("registry");
+            if dir_size(&registry) > limit {
+                let _ = writeln!(io::stderr(), "Trimming registry cache {}", registry.display());
+                trim_registry(&registry, limit);
+            }
+
+            if let Some(sccache_limit) = sccache_limit {
+                let sccache = dir.join("sccache");
+                if dir_size(&sccache) > sccache_limit {
+                    let _ = writeln!(io::stderr(), "Trimming sccache directories {}", sccache.display());
+                    trim_sccache(&sccache, sccache_limit);
+                }
+            }
+        }
+    }
+
+    // Locks the cache's lock file, shared or exclusively as `operation`
+    // says, waiting for other processes. It's unlocked when the file is
+    // closed. None if the file can't be opened, in which case other
+    // processes aren't kept out.
+    fn lock_file(&self, operation: libc::c_int) -> Option<File> {
+        let opened = fs::create_dir_all(&self.root).and_then(|_| {
+            OpenOptions::new().create(true).write(true).open(self.root.join(".lock"))
+        });
+
+        let file = match opened {
+            Ok(file) => file,
+            Err(err) => {
+                let _ = writeln!(io::stderr(), "Unable to lock the cache: {}", err);
+                return None
+            },
+        };
+
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+            let _ = writeln!(io::stderr(), "Unable to lock the cache: {}", io::Error::last_os_error());
+        }
+
+        Some(file)
+    }
+}
+
+// Removes a registry cache's extracted sources, and then its least recently
+// modified archives until it fits in `limit` bytes.
+fn trim_registry(registry: &Path, limit: u64) {
+    let _ = fs::remove_dir_all(registry.join("src"));
+
+    let mut size = dir_size(registry);
+    if size <= limit {
+        return
+    }
+
+    let mut archives = vec![];
+    collect_files(&registry.join("cache"), &mut archives);
+    archives.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (_, length, path) in archives {
+        if size <= limit {
+            break
+        }
+        if fs::remove_file(&path).is_ok() {
+            size = size.saturating_sub(length);
+        }
+    }
+}
+
+// Removes the sccache directories (`<registry>/<name>`) of the crates whose
+// caches were modified least recently until they fit in `limit` bytes.
+fn trim_sccache(sccache: &Path, limit: u64) {
+    let mut crates = vec![];
+    for registry in subdirs(sccache) {
+        for dir in subdirs(&registry) {
+            let mut files = vec![];
+            collect_files(&dir, &mut files);
+
+            let modified = files.iter().map(|&(modified, _, _)| modified).max().unwrap_or(SystemTime::now());
+            let size = files.iter().map(|&(_, length, _)| length).sum::<u64>();
+            crates.push((modified, size, dir));
+        }
+    }
+    crates.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut size = crates.iter().map(|&(_, length, _)| length).sum::<u64>();
+    for (_, length, dir) in crates {
+        if size <= limit {
+            break
+        }
+        if fs::remove_dir_all(&dir).is_ok() {
+            size = size.saturating_sub(length);
+        }
+    }
+}
+
+// Every directory directly in a directory.
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| path.is_dir()).collect(),
+        Err(_) => vec![],
+    }
+}
+
+// Total size in bytes of the files under a directory.
+fn dir_size(dir: &Path) -> u64 {
+    let mut files = vec![];
+    collect_files(dir, &mut files);
+    files.iter().map(|&(_, length, _)| length).sum()
+}
+
+// Collects the modification time, size and path of every file under a
+// directory.
+fn collect_files(dir: &Path, files: &mut Vec<(SystemTime, u64, PathBuf)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+
+        if metadata.is_dir() {
+            collect_files(&path, files);
+        } else {
+            let modified = metadata.modified().unwrap_or(SystemTime::now());
+            files.push((modified, metadata.len(), path));
+        }
+    }
+}
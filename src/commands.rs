@@ -0,0 +1,313 @@
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+
+use super::builder::{self, Builder};
+use super::cratesio::{Client, Error};
+use super::db::Db;
+use super::queue::Job;
+use super::quota::Quota;
+use super::registry::{self, Registries};
+use super::store::Store;
+use super::util::{compare_versions, crate_key};
+use super::variant::{self, Variant};
+
+/// A build claimed by `build`, which is released when it's dropped however
+/// the build ends (eg. by panicking)
+struct Claim<'a> {
+    db: &'a Mutex<Db>,
+    job: Job,
+}
+
+impl<'a> Drop for Claim<'a> {
+    fn drop(&mut self) {
+        // The database may be poisoned if the build panicked while holding it
+        let db = match self.db.lock() {
+            Ok(db) => db,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if let Err(err) = db.finish_build(&self.job) {
+            println!("Unable to release {}: {}", self.job.pair(), err.0);
+        }
+    }
+}
+
+/// Runs the build pipeline for a crate version on the current thread and
+/// prints the logs of its tasks.
+pub fn build(db: &Arc<Mutex<Db>>, store: &Store, registries: &Registries, options: &builder::Options, job: Job) -> Result<(), Error> {
+    let pair = job.pair();
+
+    if registries.get(&job.registry).is_none() {
+        return Err(Error(format!("Unknown registry {}", job.registry)))
+    }
+
+    let krate = store.for_job(&job);
+    if store.contains(&krate) {
+        println!("{} has already been built", pair);
+        return Ok(())
+    }
+
+    // The claim is leased like the server's builds, so the build is
+    // requeued if this process is killed
+    try!(Db::start_lease(db));
+    let claim = {
+        let writeable_db = db.lock().unwrap();
+
+        if !try!(writeable_db.claim_build(&job)) {
+            return Err(Error(format!("{} is already queued or being built", pair)))
+        }
+
+        try!(writeable_db.clear_failed(&pair));
+        Claim { db: db, job: job.clone() }
+    };
+
+    Builder::work(db, store, registries, options, job);
+    drop(claim);
+
+    let builds = try!(db.lock().unwrap().get_builds(&pair));
+    let build = match builds.first() {
+        Some(build) => build,
+        None => return Err(Error(format!("No build was recorded for {}", pair))),
+    };
+
+    for task in &build.tasks {
+        println!("==> {} (exit status {})", task.name, task.code);
+
+        for output in &[&task.stdout, &task.stderr] {
+            if !output.trim().is_empty() {
+                println!("{}", output.trim());
+            }
+        }
+    }
+
+    if build.succeeded {
+        println!("Built {} in {}s", pair, build.duration);
+        Ok(())
+    } else {
+        Err(Error(format!("Failed to build {}", pair)))
+    }
+}
+
+/// Prints every crate version in the store.
+pub fn list(store: &Store, registries: &Registries) {
+    for registry_name in registries.names() {
+        let registry_store = store.for_registry(&registry_name);
+
+        for name in registry_store.crate_names() {
+            for version in registry_store.crate_versions(&name).unwrap_or(vec![]) {
+                println!("{} {}", crate_key(&registry_name, &name), version);
+            }
+        }
+    }
+}
+
+/// Removes the stored docs and build records of a crate version and its
+/// variants, or of every version of the crate if no version is given.
+pub fn purge(db: &Arc<Mutex<Db>>, store: &Store, registry_name: &str, name: &str, version: Option<&str>) -> Result<(), Error> {
+    let registry_store = store.for_registry(registry_name);
+
+    let versions = match version {
+        Some(version) => vec![version.to_owned()],
+        None => {
+            match registry_store.crate_versions(name) {
+                Some(versions) => versions,
+                None => return Err(Error(format!("No docs are stored for {}", crate_key(registry_name, name)))),
+            }
+        },
+    };
+
+    for version in versions {
+        // The default docs and then each variant
+        let mut variants: Vec<Option<Variant>> = vec![None];
+        variants.extend(registry_store.crate_variants(name, &version).into_iter().map(Some));
+
+        for variant in variants {
+            let job = Job::with_variant(registry_name, name, &version, variant);
+            let krate = store.for_job(&job);
+            let pair = job.pair();
+
+            try! {
+                registry_store.remove(&krate)
+                    .map_err(|err| Error(format!("Unable to remove {}: {}", pair, err)))
+            };
+            try!(db.lock().unwrap().purge(&pair));
+
+            println!("Purged {}", pair);
+        }
+    }
+
+    if version.is_none() {
+        try! {
+            registry_store.remove_crate(name)
+                .map_err(|err| Error(format!("Unable to remove {}: {}", name, err)))
+        };
+    }
+
+    Ok(())
+}
+
+/// Archives the docs of every crate version (and variant) that's stored as a
+/// directory, as they were before docs were archived, including those stored
+/// before manifests were written.
+pub fn migrate(store: &Store, registries: &Registries) -> Result<(), Error> {
+    let mut migrated = 0;
+
+    for registry_name in registries.names() {
+        let registry_store = store.for_registry(&registry_name);
+
+        for name in registry_store.crate_names() {
+            for entry in registry_store.directory_versions(&name) {
+                let job = match variant::split_version(&entry) {
+                    Some((version, variant)) => Job::with_variant(&registry_name, &name, version, variant),
+                    None => continue,
+                };
+                let pair = job.pair();
+
+                let archived = try! {
+                    store.migrate(&store.for_job(&job))
+                        .map_err(|err| Error(format!("Unable to archive {}: {}", pair, err)))
+                };
+
+                if archived {
+                    println!("Archived {}", pair);
+                    migrated += 1;
+                }
+            }
+        }
+    }
+
+    println!("Archived {} crate versions", migrated);
+    Ok(())
+}
+
+/// Removes the blobs of file contents that no stored docs use any more (eg.
+/// after purging crates). `grace` is the most seconds a build may take (see
+/// `builder::Options::grace`).
+pub fn gc(store: &Store, grace: u64) -> Result<(), Error> {
+    // Anything staged before the longest a build may take was left behind by
+    // a build that died, and any blob written or reused before then that
+    // isn't used never will be. Staged builds go first so that blobs only
+    // they used are removed too.
+    let staged = try! {
+        store.remove_stale_staging(grace)
+            .map_err(|err| Error(format!("Unable to remove stale staged builds: {}", err)))
+    };
+    println!("Removed {} files of unfinished builds", staged);
+
+    let (removed, bytes) = try! {
+        store.remove_unused_blobs(grace)
+            .map_err(|err| Error(format!("Unable to remove unused blobs: {}", err)))
+    };
+
+    println!("Removed {} unused blobs ({} MB)", removed, bytes / 1024 / 1024);
+    Ok(())
+}
+
+/// Evicts the least recently accessed docs until the store fits in its
+/// quota.
+pub fn evict(db: &Arc<Mutex<Db>>, store: &Store, registries: &Registries, quota: Option<&Arc<Quota>>) -> Result<(), Error> {
+    let quota = match quota {
+        Some(quota) => quota,
+        None => return Err(Error("store_size isn't set, so there's nothing to evict".to_owned())),
+    };
+
+    let evicted = try! {
+        quota.enforce(db, store, registries)
+            .map_err(|err| Error(format!("Unable to evict docs: {}", err)))
+    };
+    let (evicted, freed) = match evicted {
+        Some(evicted) => evicted,
+        None => return Err(Error("Another process is evicting docs".to_owned())),
+    };
+
+    for job in &evicted {
+        println!("Evicted {}", job.pair());
+    }
+
+    println!("Evicted {} crate versions ({} MB)", evicted.len(), freed / 1024 / 1024);
+    Ok(())
+}
+
+/// Clears every recorded build failure and puts the builds back in the queue.
+pub fn retry_failed(db: &Arc<Mutex<Db>>) -> Result<(), Error> {
+    let db = db.lock().unwrap();
+    let jobs = try!(db.get_failed_jobs());
+
+    for job in &jobs {
+        try!(db.clear_failed(&job.pair()));
+        try!(db.enqueue_build(job));
+
+        println!("Queued {}", job.pair());
+    }
+
+    println!("Queued {} failed builds", jobs.len());
+    Ok(())
+}
+
+/// Queues rebuilds of every stored crate version (and variant) whose docs
+/// were built with a compiler older than `rustc_version`, or whose compiler
+/// wasn't recorded. With `latest_only` only the newest stored version of
+/// each crate is considered. The current docs are served until each rebuild
+/// succeeds and replaces them.
+pub fn rebuild(db: &Arc<Mutex<Db>>, store: &Store, registries: &Registries, rustc_version: &str, latest_only: bool) -> Result<(), Error> {
+    let db = db.lock().unwrap();
+    let mut queued = 0;
+
+    for registry_name in registries.names() {
+        let registry_store = store.for_registry(&registry_name);
+
+        for name in registry_store.crate_names() {
+            let mut versions = registry_store.crate_versions(&name).unwrap_or(vec![]);
+            versions.sort_by(|a, b| compare_versions(a, b));
+            if latest_only {
+                versions = versions.pop().into_iter().collect();
+            }
+
+            for version in versions {
+                let mut variants: Vec<Option<Variant>> = vec![None];
+                variants.extend(registry_store.crate_variants(&name, &version).into_iter().map(Some));
+
+                for variant in variants {
+                    let job = Job::rebuild(&registry_name, &name, &version, variant);
+
+                    let outdated = match store.for_job(&job).rustc_version() {
+                        Some(built_with) => compare_versions(&built_with, rustc_version) == Ordering::Less,
+                        None => true,
+                    };
+
+                    if outdated && try!(db.enqueue_build(&job)) {
+                        println!("Queued {}", job.pair());
+                        queued += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    println!("Queued {} rebuilds", queued);
+    Ok(())
+}
+
+/// Queues builds of the newest version of the `top` most downloaded crates
+/// on crates.io that aren't already in the store.
+pub fn prefetch(db: &Arc<Mutex<Db>>, store: &Store, top: usize) -> Result<(), Error> {
+    let crates = try!(Client::new().get_top_crates(top));
+    let db = db.lock().unwrap();
+
+    let mut queued = 0;
+    for (name, version) in crates {
+        let job = Job::new(registry::DEFAULT, &name, &version);
+
+        if store.contains(&store.make_crate(&name, &version)) {
+            continue
+        }
+
+        if try!(db.enqueue_build(&job)) {
+            println!("Queued {}", job.pair());
+            queued += 1;
+        }
+    }
+
+    println!("Queued {} builds", queued);
+    Ok(())
+}
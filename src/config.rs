@@ -0,0 +1,669 @@
+use getopts::{Matches, Options};
+use rustc_serialize::Decodable;
+use std::collections::BTreeMap;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use toml;
+
+use super::fallback::{self, Fallback};
+use super::package;
+use super::registry;
+use super::sandbox::{self, Limits};
+
+/// Most times a transiently failed build may be retried
+const MAX_RETRIES: u32 = 20;
+
+/// Server settings, built from (in increasing order of precedence) the
+/// defaults, a TOML config file, `DOC_SERVER_*` environment variables and
+/// command-line flags.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub redis_url: String,
+    /// Directory the built docs are stored in, unless they're stored in S3
+    pub store_path: String,
+    /// Endpoint of an S3-compatible object store (eg.
+    /// "http://localhost:9000" for MinIO) to store docs in instead of
+    /// `store_path`
+    pub s3_endpoint: Option<String>,
+    /// Bucket the docs are stored in
+    pub s3_bucket: Option<String>,
+    pub s3_region: String,
+    /// Prefix of the keys of the docs in the bucket (eg. "docs/")
+    pub s3_prefix: String,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    /// Megabytes the store may take up before the least recently accessed
+    /// docs are evicted (unlimited if `None`)
+    pub store_size: Option<u64>,
+    /// Seconds between checks of whether the store fits in `store_size`
+    pub evict_interval: u64,
+    /// Address the web server listens on (eg. "localhost:3000")
+    pub address: String,
+    pub templates_path: String,
+    pub public_path: String,
+    /// Seconds that crate metadata fetched from a registry is cached for
+    pub metadata_ttl: usize,
+    /// Seconds that a failed build is remembered for before it's retried
+    pub failure_ttl: usize,
+    pub workers: usize,
+    pub retries: u32,
+    pub retry_backoff: u64,
+    /// Local checkout of the crates.io index to look up checksums in
+    pub index: Option<String>,
+    /// Cargo local registry that crates' dependencies are vendored from, so
+    /// that builds run without network access
+    pub mirror: Option<String>,
+    /// Directory of compilation caches shared by builds, if any
+    pub cache_path: Option<String>,
+    /// Megabytes each toolchain's registry cache may take up
+    pub cache_size: u64,
+    /// Whether to cache compiled dependencies with sccache as well
+    pub sccache: bool,
+    /// Megabytes each toolchain's sccache directories may take up in total
+    pub sccache_size: u64,
+    /// Sandbox that builds run in (one of `sandbox::NAMES`)
+    pub sandbox: String,
+    /// Container image for the Docker and Podman sandboxes
+    pub image: String,
+    /// Build script run by the sandboxes that use the host's toolchain
+    pub build_script: String,
+    /// Rustup toolchain that docs are built with unless a crate needs
+    /// another (eg. "stable", "nightly" or "1.56.0")
+    pub toolchain: String,
+    /// Target triples documented for crates that don't list their own in
+    /// their docs.rs metadata. The first is the default; if empty only the
+    /// host is documented.
+    pub targets: Vec<String>,
+    /// Fallbacks tried in turn when a crate's docs fail to build (see
+    /// `Fallback::from_name`)
+    pub fallbacks: Vec<String>,
+    /// Whether to build rustdoc JSON as well as HTML
+    pub rustdoc_json: bool,
+    /// Toolchain that rustdoc JSON is built with when the build's own isn't
+    /// a nightly (the JSON output is unstable)
+    pub json_toolchain: String,
+    /// Additional registries by name (see `registry::from_spec`)
+    pub registries: BTreeMap<String, String>,
+    /// Resources each build may use
+    pub limits: Limits,
+    /// Settings for individual crates, keyed by `util::crate_key`
+    pub crates: BTreeMap<String, CrateConfig>,
+}
+
+/// Settings that apply to builds of one crate
+#[derive(Clone, Debug, Default, RustcDecodable)]
+pub struct CrateConfig {
+    /// Limits that replace the server-wide ones (eg. a longer timeout for a
+    /// crate that's slow to compile)
+    pub limits: Option<Limits>,
+    /// Toolchain to build with, replacing the server-wide one and the
+    /// crate's `rust-version`
+    pub toolchain: Option<String>,
+    /// Whether to keep every version's docs when the store is full
+    pub pinned: Option<bool>,
+    /// Versions whose docs are kept when the store is full
+    pub pinned_versions: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct ConfigError(pub String);
+
+impl error::Error for ConfigError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Contents of the config file. Everything is optional since unset values
+// fall back to the defaults.
+#[derive(Debug, RustcDecodable)]
+struct FileConfig {
+    redis_url: Option<String>,
+    store_path: Option<String>,
+    s3_endpoint: Option<String>,
+    s3_bucket: Option<String>,
+    s3_region: Option<String>,
+    s3_prefix: Option<String>,
+    s3_access_key: Option<String>,
+    s3_secret_key: Option<String>,
+    store_size: Option<u64>,
+    evict_interval: Option<u64>,
+    address: Option<String>,
+    templates_path: Option<String>,
+    public_path: Option<String>,
+    metadata_ttl: Option<usize>,
+    failure_ttl: Option<usize>,
+    workers: Option<usize>,
+    retries: Option<u32>,
+    retry_backoff: Option<u64>,
+    index: Option<String>,
+    mirror: Option<String>,
+    cache_path: Option<String>,
+    cache_size: Option<u64>,
+    sccache: Option<bool>,
+    sccache_size: Option<u64>,
+    sandbox: Option<String>,
+    image: Option<String>,
+    build_script: Option<String>,
+    toolchain: Option<String>,
+    targets: Option<Vec<String>>,
+    fallbacks: Option<Vec<String>>,
+    rustdoc_json: Option<bool>,
+    json_toolchain: Option<String>,
+    registries: Option<BTreeMap<String, String>>,
+    limits: Option<Limits>,
+    crates: Option<BTreeMap<String, CrateConfig>>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            redis_url: "redis://127.0.0.1/".to_owned(),
+            store_path: "docs".to_owned(),
+            s3_endpoint: None,
+            s3_bucket: None,
+            s3_region: "us-east-1".to_owned(),
+            s3_prefix: String::new(),
+            s3_access_key: None,
+            s3_secret_key: None,
+            store_size: None,
+            evict_interval: 600,
+            address: "localhost:3000".to_owned(),
+            templates_path: "templates/".to_owned(),
+            public_path: "public/".to_owned(),
+            metadata_ttl: 300,
+            failure_ttl: 60 * 60 * 24,
+            workers: 2,
+            retries: 3,
+            retry_backoff: 5,
+            index: None,
+            mirror: None,
+            cache_path: None,
+            cache_size: 5 * 1024,
+            sccache: false,
+            sccache_size: 10 * 1024,
+            sandbox: "docker".to_owned(),
+            image: "doc_server:build".to_owned(),
+            build_script: "script/build-doc.sh".to_owned(),
+            toolchain: "stable".to_owned(),
+            targets: vec![],
+            fallbacks: vec!["no-deps".to_owned(), "no-default-features".to_owned(), "nightly".to_owned()],
+            rustdoc_json: true,
+            json_toolchain: "nightly".to_owned(),
+            registries: BTreeMap::new(),
+            limits: Limits {
+                timeout: Some(15 * 60),
+                memory: Some(3 * 1024),
+                cpus: None,
+                processes: Some(512),
+                disk: None,
+                output: Some(1024 * 1024),
+                network: Some(false),
+            },
+            crates: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Command-line flags understood by `Config::load`
+    pub fn options() -> Options {
+        let mut opts = Options::new();
+        opts.optopt("c", "config", "path to a TOML config file", "FILE");
+        opts.optopt("", "redis-url", "URL of the Redis server", "URL");
+        opts.optopt("", "store-path", "directory the built docs are stored in", "DIR");
+        opts.optopt("", "s3-endpoint", "S3-compatible object store to store docs in instead", "URL");
+        opts.optopt("", "s3-bucket", "bucket to store docs in", "BUCKET");
+        opts.optopt("", "s3-region", "region of the bucket", "REGION");
+        opts.optopt("", "s3-prefix", "prefix of the keys of docs in the bucket", "PREFIX");
+        opts.optopt("", "store-size", "megabytes the store may use before docs are evicted", "MB");
+        opts.optopt("", "evict-interval", "seconds between checks of the store's size", "SECONDS");
+        opts.optopt("", "address", "address to listen on", "HOST:PORT");
+        opts.optopt("", "templates-path", "directory of Handlebars templates", "DIR");
+        opts.optopt("", "public-path", "directory of static files", "DIR");
+        opts.optopt("", "metadata-ttl", "seconds to cache crate metadata for", "SECONDS");
+        opts.optopt("", "failure-ttl", "seconds to remember failed builds for", "SECONDS");
+        opts.optopt("", "workers", "number of concurrent builds", "COUNT");
+        opts.optopt("", "retries", "times to retry builds that failed transiently", "COUNT");
+        opts.optopt("", "retry-backoff", "seconds before the first retry", "SECONDS");
+        opts.optopt("", "index", "local checkout of the crates.io index", "DIR");
+        opts.optopt("", "mirror", "local registry to vendor dependencies from", "DIR");
+        opts.optopt("", "cache-path", "directory of compilation caches shared by builds", "DIR");
+        opts.optopt("", "cache-size", "megabytes each registry cache may use", "MB");
+        opts.optopt("", "sccache", "whether to cache compiled dependencies with sccache (true or false)", "BOOL");
+        opts.optopt("", "sccache-size", "megabytes each toolchain's sccache directories may use", "MB");
+        opts.optopt("", "sandbox", "sandbox to build in (docker, podman, bubblewrap, nsjail or local)", "NAME");
+        opts.optopt("", "image", "container image to build in", "IMAGE");
+        opts.optopt("", "build-script", "build script for sandboxes using the host's toolchain", "FILE");
+        opts.optopt("", "toolchain", "default toolchain to build with (eg. stable or nightly)", "TOOLCHAIN");
+        opts.optopt("", "targets", "comma-separated target triples to document", "TARGETS");
+        opts.optopt("", "fallbacks", "comma-separated fallbacks to try when a build fails", "FALLBACKS");
+        opts.optopt("", "rustdoc-json", "whether to build rustdoc JSON (true or false)", "BOOL");
+        opts.optopt("", "json-toolchain", "nightly toolchain to build rustdoc JSON with", "TOOLCHAIN");
+        opts.optflag("h", "help", "print this help");
+        opts
+    }
+
+    /// Builds the configuration from parsed command-line flags, reading the
+    /// config file they point to (if any), and validates it.
+    pub fn load(matches: &Matches) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+
+        let path = matches.opt_str("config").or(env::var("DOC_SERVER_CONFIG").ok());
+        if let Some(path) = path {
+            let file = try!(read_file(&path));
+            config.merge_file(file);
+        }
+
+        try!(config.merge_overrides(|key| {
+            env::var(format!("DOC_SERVER_{}", key.to_uppercase())).ok()
+        }));
+        try!(config.merge_overrides(|key| {
+            matches.opt_str(&key.replace("_", "-"))
+        }));
+
+        // Registries can't be given as flags, so only look at the environment
+        if let Ok(specs) = env::var("DOC_SERVER_REGISTRIES") {
+            config.registries = try!(parse_registries(&specs));
+        }
+
+        // Neither can S3 credentials, so that they don't show up in `ps`
+        if let Ok(key) = env::var("DOC_SERVER_S3_ACCESS_KEY") {
+            config.s3_access_key = Some(key);
+        }
+        if let Ok(key) = env::var("DOC_SERVER_S3_SECRET_KEY") {
+            config.s3_secret_key = Some(key);
+        }
+
+        try!(config.validate());
+
+        // Make the store, mirror and cache paths absolute so that they don't depend
+        // on where commands are run from
+        let cwd = env::current_dir().unwrap();
+        config.store_path = cwd.join(&config.store_path).display().to_string();
+        config.mirror = config.mirror.map(|mirror| cwd.join(mirror).display().to_string());
+        config.cache_path = config.cache_path.map(|cache| cwd.join(cache).display().to_string());
+
+        Ok(config)
+    }
+
+    fn merge_file(&mut self, file: FileConfig) {
+        if let Some(value) = file.redis_url { self.redis_url = value }
+        if let Some(value) = file.store_path { self.store_path = value }
+        if let Some(value) = file.s3_endpoint { self.s3_endpoint = Some(value) }
+        if let Some(value) = file.s3_bucket { self.s3_bucket = Some(value) }
+        if let Some(value) = file.s3_region { self.s3_region = value }
+        if let Some(value) = file.s3_prefix { self.s3_prefix = value }
+        if let Some(value) = file.s3_access_key { self.s3_access_key = Some(value) }
+        if let Some(value) = file.s3_secret_key { self.s3_secret_key = Some(value) }
+        if let Some(value) = file.store_size { self.store_size = Some(value) }
+        if let Some(value) = file.evict_interval { self.evict_interval = value }
+        if let Some(value) = file.address { self.address = value }
+        if let Some(value) = file.templates_path { self.templates_path = value }
+        if let Some(value) = file.public_path { self.public_path = value }
+        if let Some(value) = file.metadata_ttl { self.metadata_ttl = value }
+        if let Some(value) = file.failure_ttl { self.failure_ttl = value }
+        if let Some(value) = file.workers { self.workers = value }
+        if let Some(value) = file.retries { self.retries = value }
+        if let Some(value) = file.retry_backoff { self.retry_backoff = value }
+        if let Some(value) = file.index { self.index = Some(value) }
+        if let Some(value) = file.mirror { self.mirror = Some(value) }
+        if let Some(value) = file.cache_path { self.cache_path = Some(value) }
+        if let Some(value) = file.cache_size { self.cache_size = value }
+        if let Some(value) = file.sccache { self.sccache = value }
+        if let Some(value) = file.sccache_size { self.sccache_size = value }
+        if let Some(value) = file.sandbox { self.sandbox = value }
+        if let Some(value) = file.image { self.image = value }
+        if let Some(value) = file.build_script { self.build_script = value }
+        if let Some(value) = file.toolchain { self.toolchain = value }
+        if let Some(value) = file.targets { self.targets = value }
+        if let Some(value) = file.fallbacks { self.fallbacks = value }
+        if let Some(value) = file.rustdoc_json { self.rustdoc_json = value }
+        if let Some(value) = file.json_toolchain { self.json_toolchain = value }
+        if let Some(value) = file.registries { self.registries = value }
+        if let Some(value) = file.limits { self.limits = self.limits.merge(&value) }
+        if let Some(value) = file.crates { self.crates = value }
+    }
+
+    // Overrides settings with values looked up by their key in the config file
+    // (eg. "redis_url").
+    fn merge_overrides<F>(&mut self, lookup: F) -> Result<(), ConfigError>
+        where F: Fn(&str) -> Option<String> {
+        if let Some(value) = lookup("redis_url") { self.redis_url = value }
+        if let Some(value) = lookup("store_path") { self.store_path = value }
+        if let Some(value) = lookup("s3_endpoint") { self.s3_endpoint = Some(value) }
+        if let Some(value) = lookup("s3_bucket") { self.s3_bucket = Some(value) }
+        if let Some(value) = lookup("s3_region") { self.s3_region = value }
+        if let Some(value) = lookup("s3_prefix") { self.s3_prefix = value }
+        if let Some(value) = lookup("store_size") { self.store_size = Some(try!(parse("store_size", &value))) }
+        if let Some(value) = lookup("evict_interval") { self.evict_interval = try!(parse("evict_interval", &value)) }
+        if let Some(value) = lookup("address") { self.address = value }
+        if let Some(value) = lookup("templates_path") { self.templates_path = value }
+        if let Some(value) = lookup("public_path") { self.public_path = value }
+        if let Some(value) = lookup("metadata_ttl") { self.metadata_ttl = try!(parse("metadata_ttl", &value)) }
+        if let Some(value) = lookup("failure_ttl") { self.failure_ttl = try!(parse("failure_ttl", &value)) }
+        if let Some(value) = lookup("workers") { self.workers = try!(parse("workers", &value)) }
+        if let Some(value) = lookup("retries") { self.retries = try!(parse("retries", &value)) }
+        if let Some(value) = lookup("retry_backoff") { self.retry_backoff = try!(parse("retry_backoff", &value)) }
+        if let Some(value) = lookup("index") { self.index = Some(value) }
+        if let Some(value) = lookup("mirror") { self.mirror = Some(value) }
+        if let Some(value) = lookup("cache_path") { self.cache_path = Some(value) }
+        if let Some(value) = lookup("cache_size") { self.cache_size = try!(parse("cache_size", &value)) }
+        if let Some(value) = lookup("sccache") { self.sccache = try!(parse("sccache", &value)) }
+        if let Some(value) = lookup("sccache_size") { self.sccache_size = try!(parse("sccache_size", &value)) }
+        if let Some(value) = lookup("sandbox") { self.sandbox = value }
+        if let Some(value) = lookup("image") { self.image = value }
+        if let Some(value) = lookup("build_script") { self.build_script = value }
+        if let Some(value) = lookup("toolchain") { self.toolchain = value }
+        if let Some(value) = lookup("targets") {
+            self.targets = value.split(',')
+                                .map(|target| target.trim().to_owned())
+                                .filter(|target| !target.is_empty())
+                                .collect();
+        }
+        if let Some(value) = lookup("fallbacks") {
+            self.fallbacks = value.split(',')
+                                  .map(|fallback| fallback.trim().to_owned())
+                                  .filter(|fallback| !fallback.is_empty())
+                                  .collect();
+        }
+        if let Some(value) = lookup("rustdoc_json") { self.rustdoc_json = try!(parse("rustdoc_json", &value)) }
+        if let Some(value) = lookup("json_toolchain") { self.json_toolchain = value }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !self.redis_url.starts_with("redis://") && !self.redis_url.starts_with("unix://") {
+            return Err(ConfigError(format!("redis_url must be a redis:// or unix:// URL, got {:?}", self.redis_url)))
+        }
+
+        let port = self.address.rsplitn(2, ':').next().unwrap();
+        if !self.address.contains(':') || port.parse::<u16>().is_err() {
+            return Err(ConfigError(format!("address must be of the form HOST:PORT, got {:?}", self.address)))
+        }
+
+        if let Some(ref endpoint) = self.s3_endpoint {
+            if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+                return Err(ConfigError(format!("s3_endpoint must be an http:// or https:// URL, got {:?}", endpoint)))
+            }
+            if self.s3_bucket.is_none() || self.s3_access_key.is_none() || self.s3_secret_key.is_none() {
+                return Err(ConfigError("s3_bucket, s3_access_key and s3_secret_key must be set with s3_endpoint".to_owned()))
+            }
+        }
+
+        for &(key, path) in &[("templates_path", &self.templates_path), ("public_path", &self.public_path)] {
+            if !Path::new(path).is_dir() {
+                return Err(ConfigError(format!("{} {:?} is not a directory", key, path)))
+            }
+        }
+
+        if let Some(ref index) = self.index {
+            if !Path::new(index).is_dir() {
+                return Err(ConfigError(format!("index {:?} is not a directory", index)))
+            }
+        }
+
+        if let Some(ref mirror) = self.mirror {
+            if !Path::new(mirror).join("index").is_dir() {
+                return Err(ConfigError(format!("mirror {:?} is not a cargo local registry (it has no index directory)", mirror)))
+            }
+        }
+
+        if self.store_size == Some(0) {
+            return Err(ConfigError("store_size must be at least 1".to_owned()))
+        }
+
+        if self.evict_interval == 0 {
+            return Err(ConfigError("evict_interval must be at least 1".to_owned()))
+        }
+
+        if self.cache_size == 0 || self.sccache_size == 0 {
+            return Err(ConfigError("cache_size and sccache_size must be at least 1".to_owned()))
+        }
+
+        if self.workers == 0 {
+            return Err(ConfigError("workers must be at least 1".to_owned()))
+        }
+        if self.retries > MAX_RETRIES {
+            return Err(ConfigError(format!("retries must be at most {}, got {}", MAX_RETRIES, self.retries)))
+        }
+        if self.metadata_ttl == 0 || self.failure_ttl == 0 {
+            return Err(ConfigError("metadata_ttl and failure_ttl must be at least 1 second".to_owned()))
+        }
+
+        if !sandbox::NAMES.contains(&&self.sandbox[..]) {
+            return Err(ConfigError(format!("sandbox must be one of {}, got {:?}", sandbox::NAMES.join(", "), self.sandbox)))
+        }
+        if self.sandbox != "docker" && self.sandbox != "podman" && !Path::new(&self.build_script).is_file() {
+            return Err(ConfigError(format!("build_script {:?} is not a file", self.build_script)))
+        }
+
+        try!(validate_toolchain("toolchain", &self.toolchain));
+        try!(validate_toolchain("json_toolchain", &self.json_toolchain));
+        for target in &self.targets {
+            if !package::is_target_triple(target) {
+                return Err(ConfigError(format!("targets must be target triples, got {:?}", target)))
+            }
+        }
+        for name in &self.fallbacks {
+            match Fallback::from_name(name) {
+                Some(Fallback::Toolchain(ref toolchain)) => try!(validate_toolchain("fallbacks", toolchain)),
+                Some(_) => {},
+                None => {
+                    return Err(ConfigError(format!("fallbacks must be {} or toolchain=<toolchain>, got {:?}", fallback::NAMES.join(", "), name)))
+                },
+            }
+        }
+        try!(validate_limits("limits", &self.limits));
+        for (name, krate) in &self.crates {
+            if let Some(ref toolchain) = krate.toolchain {
+                try!(validate_toolchain(&format!("crates.{}.toolchain", name), toolchain));
+            }
+            if let Some(ref limits) = krate.limits {
+                try!(validate_limits(&format!("crates.{}.limits", name), limits));
+            }
+        }
+
+        for (name, spec) in &self.registries {
+            if name == registry::DEFAULT {
+                return Err(ConfigError(format!("registry name {:?} is reserved", name)))
+            }
+            // Names are used in store keys and URLs
+            let valid = |c: char| (c >= 'a' && c <= 'z') || (c >= '0' && c <= '9') || c == '_' || c == '-';
+            if name.is_empty() || !name.chars().all(valid) {
+                return Err(ConfigError(format!("registry names may only contain a-z, 0-9, _ and -, got {:?}", name)))
+            }
+            if let Err(err) = registry::from_spec(spec) {
+                return Err(ConfigError(format!("registry {:?}: {}", name, err)))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_toolchain(key: &str, toolchain: &str) -> Result<(), ConfigError> {
+    let valid = |c: char| c.is_alphanumeric() || c == '.' || c == '-' || c == '_';
+
+    if toolchain.is_empty() || !toolchain.chars().all(valid) {
+        return Err(ConfigError(format!("{} must be a toolchain name such as \"stable\" or \"1.56.0\", got {:?}", key, toolchain)))
+    }
+
+    Ok(())
+}
+
+fn validate_limits(key: &str, limits: &Limits) -> Result<(), ConfigError> {
+    let counts = [limits.timeout, limits.memory, limits.processes, limits.disk];
+
+    if counts.iter().any(|count| *count == Some(0)) || limits.output == Some(0) {
+        return Err(ConfigError(format!("{} must be at least 1", key)))
+    }
+    if limits.cpus.map_or(false, |cpus| cpus <= 0.0) {
+        return Err(ConfigError(format!("{}: cpus must be positive", key)))
+    }
+
+    Ok(())
+}
+
+fn read_file(path: &str) -> Result<FileConfig, ConfigError> {
+    let mut contents = String::new();
+
+    try! {
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|err| ConfigError(format!("Unable to read config file {}: {}", path, err)))
+    };
+
+    parse_file(path, &contents)
+}
+
+// Parses the contents of the config file at `path`, rejecting keys that
+// aren't settings.
+fn parse_file(path: &str, contents: &str) -> Result<FileConfig, ConfigError> {
+    let mut parser = toml::Parser::new(contents);
+
+    let table = match parser.parse() {
+        Some(table) => table,
+        None => {
+            let errors: Vec<String> = parser.errors.iter().map(|err| {
+                let (line, col) = parser.to_linecol(err.lo);
+                format!("{}:{}:{}: {}", path, line + 1, col + 1, err.desc)
+            }).collect();
+
+            return Err(ConfigError(errors.join("\n")))
+        },
+    };
+
+    let mut decoder = toml::Decoder::new(toml::Value::Table(table));
+
+    let file = try! {
+        Decodable::decode(&mut decoder)
+            .map_err(|err| ConfigError(format!("{}: {}", path, err)))
+    };
+
+    // The decoder leaves behind whatever it didn't decode, ie. keys that
+    // aren't settings (such as misspelled ones)
+    let mut unknown = vec![];
+    if let Some(ref remaining) = decoder.toml {
+        unknown_keys(remaining, "", &mut unknown);
+    }
+    if !unknown.is_empty() {
+        return Err(ConfigError(format!("{}: unknown keys: {}", path, unknown.join(", "))))
+    }
+
+    Ok(file)
+}
+
+// Adds the dotted keys of every value in some TOML to `keys`.
+fn unknown_keys(value: &toml::Value, key: &str, keys: &mut Vec<String>) {
+    match *value {
+        toml::Value::Table(ref table) => {
+            for (name, value) in table {
+                let key = if key.is_empty() { name.clone() } else { format!("{}.{}", key, name) };
+                unknown_keys(value, &key, keys);
+            }
+        },
+        toml::Value::Array(ref values) => {
+            for value in values {
+                unknown_keys(value, key, keys);
+            }
+        },
+        _ => keys.push(key.to_owned()),
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse::<T>()
+        .map_err(|_| ConfigError(format!("Invalid value for {}: {:?}", key, value)))
+}
+
+// Parses semicolon-separated "name=spec" pairs.
+fn parse_registries(specs: &str) -> Result<BTreeMap<String, String>, ConfigError> {
+    let mut registries = BTreeMap::new();
+
+    for definition in specs.split(';').filter(|definition| !definition.trim().is_empty()) {
+        let mut parts = definition.splitn(2, '=');
+        let name = parts.next().unwrap().trim();
+
+        match parts.next() {
+            Some(spec) => { registries.insert(name.to_owned(), spec.trim().to_owned()); },
+            None => return Err(ConfigError(format!("Registry {:?} must be given as name=spec", definition))),
+        }
+    }
+
+    Ok(registries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_file, Config, ConfigError, MAX_RETRIES};
+
+    fn error(result: Result<(), ConfigError>) -> String {
+        match result {
+            Ok(()) => panic!("expected an error"),
+            Err(err) => err.0,
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let err = parse_file("doc_server.toml", "workers = 2\nwokers = 3").unwrap_err();
+        assert_eq!(err.0, "doc_server.toml: unknown keys: wokers");
+    }
+
+    #[test]
+    fn rejects_unknown_nested_keys() {
+        let err = parse_file("doc_server.toml", "[limits]\ntimeout = 60\ntimout = 60\n\n[crates.foo]\npined = true").unwrap_err();
+        assert_eq!(err.0, "doc_server.toml: unknown keys: crates.foo.pined, limits.timout");
+    }
+
+    #[test]
+    fn environment_overrides_file_overrides_defaults() {
+        let mut config = Config::default();
+        config.merge_file(parse_file("doc_server.toml", "workers = 4\nretries = 5").unwrap());
+        config.merge_overrides(|key| if key == "workers" { Some("8".to_owned()) } else { None }).unwrap();
+
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.retries, 5);
+        assert_eq!(config.retry_backoff, Config::default().retry_backoff);
+    }
+
+    #[test]
+    fn rejects_invalid_registry_names() {
+        let mut config = Config::default();
+        config.registries.insert("My Registry".to_owned(), "sparse+https://example.com/index/".to_owned());
+        assert!(error(config.validate()).starts_with("registry names may only contain"));
+
+        let mut config = Config::default();
+        config.registries.insert("crates-io".to_owned(), "sparse+https://example.com/index/".to_owned());
+        assert!(error(config.validate()).contains("is reserved"));
+    }
+
+    #[test]
+    fn validates_retries_and_timeout() {
+        let mut config = Config::default();
+        config.retries = MAX_RETRIES;
+        config.validate().unwrap();
+
+        config.retries = MAX_RETRIES + 1;
+        assert_eq!(error(config.validate()), format!("retries must be at most {}, got {}", MAX_RETRIES, MAX_RETRIES + 1));
+
+        let mut config = Config::default();
+        config.limits.timeout = Some(0);
+        assert_eq!(error(config.validate()), "limits must be at least 1");
+
+        let mut config = Config::default();
+        assert!(error(config.merge_overrides(|key| if key == "retries" { Some("many".to_owned()) } else { None })).starts_with("Invalid value for retries"));
+    }
+}
@@ -0,0 +1,57 @@
+use rustc_serialize::json::Json;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use super::Error;
+
+/// Path of a crate's file within a registry index (eg. "se/rd/serde").
+pub fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    format!("{}/{}", prefix(&name), name)
+}
+
+/// Directories a crate's file is nested in within an index (eg. "se/rd").
+pub fn prefix(name: &str) -> String {
+    match name.len() {
+        1 => "1".to_owned(),
+        2 => "2".to_owned(),
+        3 => format!("3/{}", &name[..1]),
+        _ => format!("{}/{}", &name[..2], &name[2..4]),
+    }
+}
+
+/// Finds a version's checksum in the contents of a crate's index file, which
+/// has one JSON object per published version.
+pub fn find_checksum(contents: &str, version: &str) -> Option<String> {
+    for line in contents.lines() {
+        let entry = match Json::from_str(line) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+
+        let vers = entry.find("vers").and_then(|vers| vers.as_string());
+
+        if vers == Some(version) {
+            return entry.find("cksum")
+                .and_then(|cksum| cksum.as_string())
+                .map(|cksum| cksum.to_owned())
+        }
+    }
+
+    None
+}
+
+/// Looks up a version's checksum in a local checkout of a registry index.
+pub fn get_local_checksum(index: &str, name: &str, version: &str) -> Result<Option<String>, Error> {
+    let path = Path::new(index).join(index_path(name));
+
+    let mut contents = String::new();
+    try! {
+        File::open(&path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|err| Error(format!("Unable to read {}: {}", path.display(), err)))
+    };
+
+    Ok(find_checksum(&contents, version))
+}
@@ -1,8 +1,11 @@
 use hyper::client::{Client as HyperClient};
+use hyper::status::StatusCode;
 use rustc_serialize::json::Json;
 use std::error;
 use std::fmt;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
+
+pub mod index;
 
 pub struct Client {
     client: HyperClient,
@@ -63,6 +66,69 @@ impl Client {
             })
     }
 
+    /// Returns the name and newest version of the `count` most downloaded
+    /// crates.
+    pub fn get_top_crates(&self, count: usize) -> Result<Vec<(String, String)>, Error> {
+        let per_page = 100;
+        let mut crates = vec![];
+        let mut page = 1;
+
+        while crates.len() < count {
+            let url = Client::url(format!("/crates?sort=downloads&per_page={}&page={}", per_page, page));
+
+            let json = try! {
+                self.client.get(&url)
+                    .send().map_err(format_error)
+                    .and_then(|response| {
+                        let mut reader = BufReader::new(response);
+                        Json::from_reader(&mut reader).map_err(format_error)
+                    })
+            };
+
+            let entries = match json.find("crates").and_then(|crates| crates.as_array()) {
+                Some(entries) => entries.clone(),
+                None => return Err(Error("Unexpected response listing crates".to_owned())),
+            };
+
+            if entries.is_empty() {
+                break
+            }
+
+            for entry in entries {
+                let name = entry.find("name").and_then(|name| name.as_string());
+                let version = entry.find("max_version").and_then(|version| version.as_string());
+
+                if let (Some(name), Some(version)) = (name, version) {
+                    crates.push((name.to_owned(), version.to_owned()));
+                }
+            }
+
+            page += 1;
+        }
+
+        crates.truncate(count);
+        Ok(crates)
+    }
+
+    /// Looks up the SHA-256 checksum of a crate version in the crates.io
+    /// sparse index. The index responds 404 for crates it doesn't have; any
+    /// other unsuccessful response is an error, since it may be transient.
+    pub fn get_checksum(&self, name: &str, version: &str) -> Result<Option<String>, Error> {
+        let url = format!("https://index.crates.io/{}", index::index_path(name));
+
+        let mut response = try!(self.client.get(&url).send().map_err(format_error));
+        match response.status {
+            StatusCode::NotFound => return Ok(None),
+            status if !status.is_success() => return Err(Error(format!("{} responded with {}", url, status))),
+            _ => {},
+        }
+
+        let mut contents = String::new();
+        try!(response.read_to_string(&mut contents).map_err(format_error));
+
+        Ok(index::find_checksum(&contents, version))
+    }
+
     fn url(path: String) -> String {
         format!("https://crates.io/api/v1{}", path)
     }
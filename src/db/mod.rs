@@ -2,13 +2,20 @@ use iron::typemap;
 use redis::{self, Commands, RedisError};
 use rustc_serialize::json;
 use rustc_serialize::{Encodable, Decodable};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error;
-use std::sync::{Arc, RwLock};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
 
 use super::builder::Builder;
+use super::config::Config;
 use super::cratesio::{Error, Metadata};
+use super::queue::Job;
 use super::store::StoredCrate;
+use super::util::now;
 
 pub mod util;
 
@@ -17,6 +24,15 @@ pub use self::util::GetDb;
 pub struct Db {
     redis_con: redis::Connection,
 
+    /// Seconds that crate metadata is cached for
+    pub metadata_ttl: usize,
+    /// Seconds that failed builds are remembered for
+    pub failure_ttl: usize,
+
+    /// Unique name of this process, which owns the builds it takes off the
+    /// queue (see `renew_lease`)
+    owner: String,
+
     builds_in_progress: HashMap<StoredCrate, Arc<RwLock<Builder>>>,
 }
 
@@ -25,43 +41,202 @@ pub struct Db {
 pub struct FailedModel {
     pub code: i32,
     pub message: String,
+    /// Build stage that failed (eg. "download" or "doc")
+    pub stage: String,
+    /// Classification of the failure (eg. "network" or "compile")
+    pub category: String,
+    /// Number of times the build was attempted before giving up
+    pub attempts: u32,
+}
+
+/// Record of a single attempt at building a crate version's documentation
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct BuildModel {
+    /// Start and end of the build in seconds since the Unix epoch
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub duration: u64,
+    pub succeeded: bool,
+    /// Toolchain and sandbox the build ran with
+    pub toolchain: String,
+    /// Compiler version reported by the build, if it got that far
+    pub rustc: Option<String>,
+    /// Compiler cache hits and misses, if the build used sccache
+    pub cache_hits: Option<u64>,
+    pub cache_misses: Option<u64>,
+    pub tasks: Vec<TaskModel>,
+}
+
+/// Exit status and output of one of the tasks in a build
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct TaskModel {
+    pub name: String,
+    pub succeeded: bool,
+    pub code: i32,
+    pub stdout: String,
+    pub stderr: String,
 }
 
+/// Number of build records kept for each crate version
+const MAX_BUILDS: isize = 20;
+
+/// Seconds that a process's claim on the builds it took off the queue lasts
+/// without being renewed. Builds of processes whose lease has run out (eg.
+/// because they were killed) are requeued.
+const LEASE: usize = 60;
+
+/// Requeues the builds of every process whose lease has run out, onto the
+/// end of the queue that jobs are taken from so that they're built next,
+/// and forgets those processes. Returns the number of builds requeued.
+const REQUEUE_SCRIPT: &'static str = r"
+local requeued = 0
+for _, owner in ipairs(redis.call('SMEMBERS', 'owners')) do
+    if redis.call('EXISTS', 'lease:' .. owner) == 0 then
+        local processing = 'processing:' .. owner
+        for _, job in ipairs(redis.call('LRANGE', processing, 0, -1)) do
+            redis.call('RPUSH', 'queue', job)
+            requeued = requeued + 1
+        end
+        redis.call('DEL', processing)
+        redis.call('SREM', 'owners', owner)
+    end
+end
+return requeued
+";
+
+/// Releases a lock if this process (`ARGV[1]`) still holds it, rather than
+/// one that took it after it expired.
+const UNLOCK_SCRIPT: &'static str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
 impl typemap::Key for Db { type Value = Db; }
 
 impl Db {
-    pub fn new(redis_url: &str) -> Db {
-        let client = redis::Client::open(redis_url).unwrap();
-        let con    = client.get_connection().unwrap();
+    pub fn new(config: &Config) -> Result<Db, RedisError> {
+        let client = try!(redis::Client::open(&config.redis_url[..]));
+        let con    = try!(client.get_connection());
 
-        Db {
+        Ok(Db {
             redis_con: con,
+            metadata_ttl: config.metadata_ttl,
+            failure_ttl: config.failure_ttl,
+            owner: Uuid::new_v4().to_simple_string(),
             builds_in_progress: HashMap::new(),
-        }
+        })
     }
 
-    // krate: Name-version pair
-    pub fn set_failed(&self, krate: &str, failed: FailedModel) -> Result<(), Error> {
-        let key = format!("failed:{}", krate);
+    pub fn set_failed(&self, job: &Job, failed: FailedModel) -> Result<(), Error> {
+        let key = format!("failed:{}", job.pair());
         let data = json::encode(&failed).unwrap();
 
         let result = self.redis_con.set(key.clone(), data)
-            .map_err(|err| Error(format!("{}", err)));
+            .map_err(redis_error);
+
+        let _: Result<(), RedisError> = self.redis_con.expire(key, self.failure_ttl);
 
-        let _: Result<(), RedisError> = self.redis_con.expire(key, 60 * 60 * 24); // Expire after a day
+        // Keep the job around so that the build can be retried later
+        let _: Result<(), RedisError> = self.redis_con.hset("failed_jobs", job.pair(), json::encode(job).unwrap());
 
         result
     }
 
+    // krate: Name-version pair
+    pub fn clear_failed(&self, krate: &str) -> Result<(), Error> {
+        let _: () = try! { self.redis_con.del(format!("failed:{}", krate)).map_err(redis_error) };
+        self.redis_con.hdel("failed_jobs", krate).map_err(redis_error)
+    }
+
+    /// Returns the jobs for every crate version whose build has failed (and
+    /// hasn't yet expired).
+    pub fn get_failed_jobs(&self) -> Result<Vec<Job>, Error> {
+        let jobs: HashMap<String, String> = try! { self.redis_con.hgetall("failed_jobs").map_err(redis_error) };
+        let mut failed_jobs = vec![];
+
+        for (pair, data) in jobs {
+            let failed: bool = try! { self.redis_con.exists(format!("failed:{}", pair)).map_err(redis_error) };
+
+            if failed {
+                failed_jobs.extend(json::decode::<Job>(&data).ok());
+            } else {
+                let _: () = try! { self.redis_con.hdel("failed_jobs", pair).map_err(redis_error) };
+            }
+        }
+
+        Ok(failed_jobs)
+    }
+
+    /// Records that a crate version's docs were just accessed, so that the
+    /// least recently accessed are evicted first when the store is full.
+    pub fn record_access(&self, krate: &str) {
+        let _: Result<(), RedisError> = self.redis_con.hset("accessed", krate, now());
+    }
+
+    /// Returns when each crate version's docs were last accessed, in seconds
+    /// since the Unix epoch.
+    pub fn get_access_times(&self) -> HashMap<String, u64> {
+        self.redis_con.hgetall("accessed").unwrap_or(HashMap::new())
+    }
+
+    pub fn forget_access(&self, krate: &str) {
+        let _: Result<(), RedisError> = self.redis_con.hdel("accessed", krate);
+    }
+
+    /// Forgets everything recorded about a crate version's builds.
+    pub fn purge(&self, krate: &str) -> Result<(), Error> {
+        try!(self.clear_failed(krate));
+        self.forget_access(krate);
+
+        let keys = vec![format!("builds:{}", krate), format!("attempts:{}", krate)];
+        self.redis_con.del(keys).map_err(redis_error)
+    }
+
     pub fn get_failed(&self, krate: &str) -> Option<FailedModel> {
         let key = format!("failed:{}", krate);
         let result: Option<String> = self.redis_con.get(key).unwrap();
 
-        result.map(|data| {
-            json::decode::<FailedModel>(&data).unwrap()
+        // Records from before failures were classified won't decode
+        result.and_then(|data| {
+            json::decode::<FailedModel>(&data).ok()
         })
     }
 
+    /// Records a build attempt. Builds are kept newest-first.
+    pub fn add_build(&self, krate: &str, build: &BuildModel) -> Result<(), Error> {
+        let key = format!("builds:{}", krate);
+        let data = json::encode(build).unwrap();
+
+        let _: () = try! { self.redis_con.lpush(key.clone(), data).map_err(redis_error) };
+        self.redis_con.ltrim(key, 0, MAX_BUILDS - 1).map_err(redis_error)
+    }
+
+    pub fn get_builds(&self, krate: &str) -> Result<Vec<BuildModel>, Error> {
+        let key = format!("builds:{}", krate);
+        let builds: Vec<String> = try! { self.redis_con.lrange(key, 0, -1).map_err(redis_error) };
+
+        builds.iter().map(|data| {
+            json::decode::<BuildModel>(data)
+                .map_err(|err| Error(format!("Invalid build record of {}: {}", krate, err)))
+        }).collect()
+    }
+
+    /// Records which attempt at building the crate version is in progress.
+    pub fn set_attempts(&self, krate: &str, attempts: u32) {
+        let key = format!("attempts:{}", krate);
+
+        // Only shown while the build runs, so it isn't worth failing over
+        let _: Result<(), RedisError> = self.redis_con.set(key.clone(), attempts);
+        let _: Result<(), RedisError> = self.redis_con.expire(key, self.failure_ttl);
+    }
+
+    pub fn get_attempts(&self, krate: &str) -> Option<u32> {
+        let key = format!("attempts:{}", krate);
+        self.redis_con.get(key).unwrap()
+    }
+
     // expire_in: Also set time-to-live in second
     pub fn get_crate<F>(&self, name: &str, fetch: F, expire_in: Option<usize>) -> Result<Metadata, Error>
         where F: FnOnce() -> Result<Metadata, Error> {
@@ -81,22 +256,191 @@ impl Db {
         metadata
     }
 
-    pub fn add_build_in_progress(&mut self, builder: Arc<RwLock<Builder>>) {
-        let dest = builder.read().unwrap().dest.clone();
+    /// Appends a job to the build queue. Returns `false` if the crate version
+    /// is already queued or being built.
+    pub fn enqueue_build(&self, job: &Job) -> Result<bool, Error> {
+        let added: usize = try! {
+            self.redis_con.sadd("queued", job.pair()).map_err(redis_error)
+        };
+
+        if added == 0 {
+            return Ok(false)
+        }
+
+        // Jobs are pushed onto the head of the queue and taken off its tail
+        let data = json::encode(job).unwrap();
+        let _: () = try! { self.redis_con.lpush("queue", data).map_err(redis_error) };
+
+        Ok(true)
+    }
+
+    /// Marks a job as being built by this process without putting it in the
+    /// queue, for builds that are run directly. Returns `false` if it's
+    /// already queued or being built. Like a job taken off the queue, it's
+    /// requeued if the process dies before `finish_build` is called.
+    pub fn claim_build(&self, job: &Job) -> Result<bool, Error> {
+        let added: usize = try! {
+            self.redis_con.sadd("queued", job.pair()).map_err(redis_error)
+        };
 
-        self.builds_in_progress.insert(dest, builder);
+        if added == 0 {
+            return Ok(false)
+        }
+
+        let data = json::encode(job).unwrap();
+        let _: () = try! { self.redis_con.lpush(self.processing_key(), data).map_err(redis_error) };
+
+        Ok(true)
     }
 
-    pub fn remove_build_in_progress(&mut self, builder: Arc<RwLock<Builder>>) {
-        let ref dest = builder.read().unwrap().dest;
+    /// Takes the next job off the queue. The job is moved onto this
+    /// process's processing list in the same command, and stays there until
+    /// `finish_build` is called, so that it's requeued if the process dies at
+    /// any point during the build.
+    pub fn dequeue_build(&self) -> Result<Option<Job>, Error> {
+        let data: Option<String> = try! {
+            self.redis_con.rpoplpush("queue", self.processing_key()).map_err(redis_error)
+        };
 
-        self.builds_in_progress.remove(dest);
+        match data {
+            Some(data) => {
+                match json::decode::<Job>(&data) {
+                    Ok(job) => Ok(Some(job)),
+                    Err(err) => {
+                        // Drop the job rather than failing on it forever
+                        let _: Result<(), RedisError> = self.redis_con.lrem(self.processing_key(), 1, data.clone());
+                        Err(Error(format!("Dropped invalid job {:?} from the queue: {}", data, err)))
+                    },
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn finish_build(&self, job: &Job) -> Result<(), Error> {
+        let data = json::encode(job).unwrap();
+
+        let _: () = try! { self.redis_con.lrem(self.processing_key(), 1, data).map_err(redis_error) };
+        self.redis_con.srem("queued", job.pair()).map_err(redis_error)
+    }
+
+    /// Renews this process's lease on the builds it has taken off the queue
+    /// or claimed. It must be renewed more often than every `LEASE` seconds
+    /// while it has any, which `start_lease` does.
+    pub fn renew_lease(&self) -> Result<(), Error> {
+        let key = format!("lease:{}", self.owner);
+
+        let _: () = try! { self.redis_con.sadd("owners", &self.owner[..]).map_err(redis_error) };
+        let _: () = try! { self.redis_con.set(key.clone(), now()).map_err(redis_error) };
+        self.redis_con.expire(key, LEASE).map_err(redis_error)
+    }
+
+    /// Renews the process's lease now and then every third of `LEASE` on a
+    /// background thread, which also requeues the builds of processes that
+    /// have died in the meantime.
+    pub fn start_lease(db: &Arc<Mutex<Db>>) -> Result<(), Error> {
+        try!(db.lock().unwrap().renew_lease());
+
+        let db = db.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(LEASE as u64 / 3));
+
+                let db = db.lock().unwrap();
+                let result = db.renew_lease().and_then(|_| db.requeue_interrupted_builds());
+                if let Err(err) = result {
+                    let _ = write!(io::stderr(), "Unable to renew the lease on builds: {}\n", err.0);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Puts builds that were interrupted because the process building them
+    /// died (ie. its lease ran out) back on the queue to be built next.
+    /// Builds of processes that are still running are left alone.
+    pub fn requeue_interrupted_builds(&self) -> Result<usize, Error> {
+        redis::Script::new(REQUEUE_SCRIPT).invoke(&self.redis_con).map_err(redis_error)
+    }
+
+    /// Forgets that crate versions are queued or being built when they're
+    /// in neither the queue nor a live process's processing list, so that
+    /// they can be queued again. Their claims were left behind by processes
+    /// that died before claims were leased. Only run it at startup, since a
+    /// job being queued meanwhile could lose its claim.
+    pub fn reconcile_queued(&self) -> Result<(), Error> {
+        let mut lists = vec!["queue".to_owned()];
+        let owners: Vec<String> = try! { self.redis_con.smembers("owners").map_err(redis_error) };
+        lists.extend(owners.iter().map(|owner| format!("processing:{}", owner)));
+
+        let mut pending = HashSet::new();
+        for list in lists {
+            let jobs: Vec<String> = try! { self.redis_con.lrange(list, 0, -1).map_err(redis_error) };
+            pending.extend(jobs.iter().filter_map(|data| json::decode::<Job>(data).ok()).map(|job| job.pair()));
+        }
+
+        let queued: Vec<String> = try! { self.redis_con.smembers("queued").map_err(redis_error) };
+        for pair in queued {
+            if !pending.contains(&pair) {
+                let _: () = try! { self.redis_con.srem("queued", pair).map_err(redis_error) };
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether a crate version is waiting to be built, or being built by any
+    /// process.
+    pub fn is_queued(&self, pair: &str) -> Result<bool, Error> {
+        self.redis_con.sismember("queued", pair).map_err(redis_error)
+    }
+
+    /// Number of jobs waiting to be built
+    pub fn queue_length(&self) -> Result<usize, Error> {
+        self.redis_con.llen("queue").map_err(redis_error)
+    }
+
+    /// Takes the lock `name`, shared by every process using the same Redis,
+    /// for `ttl` seconds unless another process holds it. Returns whether
+    /// it was taken.
+    pub fn try_lock(&self, name: &str, ttl: usize) -> Result<bool, Error> {
+        let taken: Option<String> = try! {
+            redis::cmd("SET").arg(format!("lock:{}", name)).arg(&self.owner[..]).arg("NX").arg("EX").arg(ttl)
+                .query(&self.redis_con)
+                .map_err(redis_error)
+        };
+
+        Ok(taken.is_some())
+    }
+
+    /// Releases the lock `name`, if this process holds it.
+    pub fn unlock(&self, name: &str) -> Result<(), Error> {
+        redis::Script::new(UNLOCK_SCRIPT)
+            .key(format!("lock:{}", name))
+            .arg(&self.owner[..])
+            .invoke(&self.redis_con)
+            .map_err(redis_error)
+    }
+
+    /// Records that a crate version's docs are being built by `builder`.
+    pub fn add_build_in_progress(&mut self, krate: StoredCrate, builder: Arc<RwLock<Builder>>) {
+        self.builds_in_progress.insert(krate, builder);
+    }
+
+    pub fn remove_build_in_progress(&mut self, krate: &StoredCrate) {
+        self.builds_in_progress.remove(krate);
     }
 
     pub fn is_build_in_progress(&self, krate: &StoredCrate) -> bool {
         self.builds_in_progress.contains_key(krate)
     }
 
+    // Key of the list of the builds this process is running
+    fn processing_key(&self) -> String {
+        format!("processing:{}", self.owner)
+    }
+
     fn fetch<F, T, E>(&self, key: String, fetch: F) -> Result<T, E>
         where F: FnOnce() -> Result<T, E>,
               T: Encodable + Decodable,
@@ -119,3 +463,7 @@ impl Db {
         }
     }
 }
+
+fn redis_error(err: RedisError) -> Error {
+    Error(format!("{}", err))
+}
@@ -0,0 +1,74 @@
+use super::package::DocsMetadata;
+
+/// A change to how a crate's docs are built, tried in turn when building
+/// them as its docs.rs metadata asks fails. Docs built by a fallback are
+/// labelled with its name since they may differ from those on docs.rs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Fallback {
+    /// Document the crate without its dependencies
+    NoDeps,
+    /// Turn off the crate's default features
+    NoDefaultFeatures,
+    /// Build with another (usually older) toolchain
+    Toolchain(String),
+    /// Build on nightly, only for crates whose build failed because they use
+    /// `#![feature]`
+    Nightly,
+}
+
+/// Names of the fallbacks besides `toolchain=<toolchain>`
+pub const NAMES: &'static [&'static str] = &["no-deps", "no-default-features", "nightly"];
+
+impl Fallback {
+    /// Parses a fallback's name (eg. "no-deps" or "toolchain=1.70.0").
+    pub fn from_name(name: &str) -> Option<Fallback> {
+        match name {
+            "no-deps" => Some(Fallback::NoDeps),
+            "no-default-features" => Some(Fallback::NoDefaultFeatures),
+            "nightly" => Some(Fallback::Nightly),
+            _ if name.starts_with("toolchain=") => {
+                Some(Fallback::Toolchain(name["toolchain=".len()..].to_owned()))
+            },
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            Fallback::NoDeps => "no-deps".to_owned(),
+            Fallback::NoDefaultFeatures => "no-default-features".to_owned(),
+            Fallback::Toolchain(ref toolchain) => format!("toolchain={}", toolchain),
+            Fallback::Nightly => "nightly".to_owned(),
+        }
+    }
+
+    /// Whether the fallback is worth trying after a build with `toolchain`
+    /// failed with `output`.
+    pub fn applies(&self, toolchain: &str, output: &str) -> bool {
+        match *self {
+            // E0554: `#![feature]` may not be used on the stable release
+            // channel
+            Fallback::Nightly => !toolchain.starts_with("nightly") && output.contains("E0554"),
+            Fallback::Toolchain(ref fallback) => fallback != toolchain,
+            _ => true,
+        }
+    }
+
+    /// Returns the docs.rs metadata and toolchain changed by the fallback.
+    pub fn apply(&self, metadata: &DocsMetadata, toolchain: &str) -> (DocsMetadata, String) {
+        let mut metadata = metadata.clone();
+        let mut toolchain = toolchain.to_owned();
+
+        match *self {
+            Fallback::NoDeps => metadata.no_deps = true,
+            Fallback::NoDefaultFeatures => {
+                metadata.no_default_features = true;
+                metadata.all_features = false;
+            },
+            Fallback::Toolchain(ref fallback) => toolchain = fallback.clone(),
+            Fallback::Nightly => toolchain = "nightly".to_owned(),
+        }
+
+        (metadata, toolchain)
+    }
+}
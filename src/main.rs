@@ -1,9 +1,12 @@
 #[macro_use]
 extern crate maplit;
 
+extern crate crypto;
+extern crate getopts;
 extern crate handlebars_iron;
 extern crate hyper;
 extern crate iron;
+extern crate libc;
 extern crate mount;
 extern crate persistent;
 extern crate plugin;
@@ -12,7 +15,10 @@ extern crate router;
 extern crate route_recognizer;
 extern crate rustc_serialize;
 extern crate staticfile;
+extern crate toml;
 extern crate uuid;
+extern crate wait_timeout;
+extern crate zstd;
 
 use handlebars_iron::{DirectorySource, HandlebarsEngine};
 use hyper::method::Method;
@@ -23,55 +29,223 @@ use router::Router;
 use staticfile::Static;
 use std::env;
 use std::error::Error;
-use std::path::Path;
+use std::io::{self, Write as IoWrite};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
 
 pub mod cratesio;
 mod builder;
+mod cache;
+mod commands;
+mod config;
 mod db;
+mod fallback;
+mod package;
+mod queue;
+mod quota;
+mod registry;
+mod sandbox;
 mod store;
 mod tasks;
 mod temp_crate;
 mod util;
+mod variant;
 mod web;
 
 pub use self::temp_crate::TempCrate;
+use builder::Builder;
+use cache::Cache;
+use config::Config;
 use db::Db;
-use store::Store;
+use fallback::Fallback;
+use queue::Job;
+use quota::Quota;
+use registry::{CratesIo, Registries};
+use store::{Backend, FileSystem, Store, S3};
+
+const USAGE: &'static str = "Usage: doc_server [options] [COMMAND]
+
+Commands:
+    serve                    Run the web server and build workers (default)
+    build NAME VERSION       Build a crate version's docs and print the logs
+    list                     List every crate version in the store
+    purge NAME [VERSION]     Remove stored docs and build records
+    retry-failed             Queue every failed build to be tried again
+    rebuild RUSTC_VERSION    Queue rebuilds of docs built with an older rustc
+    migrate                  Archive docs stored as directories
+    gc                       Remove unused file contents and unfinished builds
+    evict                    Evict least recently used docs until the store fits
+    prefetch --top N         Queue builds of the N most downloaded crates";
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+
+    let mut opts = Config::options();
+    opts.optopt("", "registry", "registry of the crate for build and purge", "NAME");
+    opts.optopt("", "top", "number of crates to prefetch", "N");
+    opts.optflag("", "latest", "only rebuild the newest version of each crate");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(matches) => matches,
+        Err(err) => exit_with_error(&err.to_string()),
+    };
+
+    if matches.opt_present("help") {
+        print!("{}", opts.usage(USAGE));
+        return
+    }
+
+    let config = match Config::load(&matches) {
+        Ok(config) => config,
+        Err(err) => exit_with_error(&err.0),
+    };
+
+    let db = match Db::new(&config) {
+        Ok(db) => Arc::new(Mutex::new(db)),
+        Err(err) => exit_with_error(&format!("Unable to connect to Redis: {}", err)),
+    };
+
+    let backend: Arc<Backend> = match config.s3_endpoint {
+        Some(ref endpoint) => Arc::new(S3::new(endpoint,
+                                               config.s3_bucket.as_ref().unwrap(),
+                                               &config.s3_region,
+                                               &config.s3_prefix,
+                                               config.s3_access_key.as_ref().unwrap(),
+                                               config.s3_secret_key.as_ref().unwrap())),
+        None => Arc::new(FileSystem::new(PathBuf::from(&config.store_path))),
+    };
+    let store = Arc::new(Store::new(backend));
+
+    let mut options = builder::Options {
+        workers: config.workers,
+        retries: config.retries,
+        retry_backoff: config.retry_backoff,
+        sandbox: sandbox::from_name(&config.sandbox, &config.image, &config.build_script).unwrap(),
+        limits: config.limits.clone(),
+        toolchain: config.toolchain.clone(),
+        targets: config.targets.clone(),
+        json_toolchain: if config.rustdoc_json { Some(config.json_toolchain.clone()) } else { None },
+        mirror: config.mirror.clone(),
+        cache: config.cache_path.as_ref().map(|path| {
+            let sccache_size = if config.sccache { Some(config.sccache_size) } else { None };
+            Arc::new(Cache::new(PathBuf::from(path), config.cache_size, sccache_size))
+        }),
+        fallbacks: config.fallbacks.iter().filter_map(|name| Fallback::from_name(name)).collect(),
+        crates: config.crates.clone(),
+        quota: None,
+    };
+    let grace = options.grace();
+    options.quota = config.store_size.map(|size| Arc::new(Quota::new(size, config.crates.clone(), grace)));
+
+    let registries = Arc::new(get_registries(&config));
+
+    let registry_name = matches.opt_str("registry").unwrap_or(registry::DEFAULT.to_owned());
+    let command = matches.free.get(0).map(|arg| &arg[..]).unwrap_or("serve");
+    let command_args: Vec<&str> = matches.free.iter().skip(1).map(|arg| &arg[..]).collect();
+
+    let result = match (command, command_args.len()) {
+        ("serve", 0) => {
+            serve(&config, db, store, registries, options);
+            Ok(())
+        },
+        ("build", 2) => {
+            let job = Job::new(&registry_name, command_args[0], command_args[1]);
+            commands::build(&db, &store, &registries, &options, job)
+        },
+        ("list", 0) => {
+            commands::list(&store, &registries);
+            Ok(())
+        },
+        ("purge", 1) | ("purge", 2) => {
+            let version = command_args.get(1).map(|version| *version);
+            commands::purge(&db, &store, &registry_name, command_args[0], version)
+        },
+        ("retry-failed", 0) => commands::retry_failed(&db),
+        ("migrate", 0) => commands::migrate(&store, &registries),
+        ("gc", 0) => commands::gc(&store, options.grace()),
+        ("evict", 0) => commands::evict(&db, &store, &registries, options.quota.as_ref()),
+        ("rebuild", 1) => {
+            commands::rebuild(&db, &store, &registries, command_args[0], matches.opt_present("latest"))
+        },
+        ("prefetch", 0) => {
+            match matches.opt_str("top").and_then(|top| top.parse::<usize>().ok()) {
+                Some(top) => commands::prefetch(&db, &store, top),
+                None => exit_with_error("prefetch requires --top N"),
+            }
+        },
+        _ => exit_with_error(&opts.usage(USAGE)),
+    };
+
+    if let Err(err) = result {
+        exit_with_error(&err.0);
+    }
+}
+
+fn serve(config: &Config, db: Arc<Mutex<Db>>, store: Arc<Store>, registries: Arc<Registries>, options: builder::Options) {
     use self::web::api;
     use self::web::frontend;
 
-    let db = Db::new("redis://127.0.0.1/");
-
-    let cwd = env::current_dir().unwrap();
-    let store = Store::new(format!("{}/docs", cwd.display()));
+    if let Some(ref quota) = options.quota {
+        Quota::start(quota.clone(), config.evict_interval, db.clone(), store.clone(), registries.clone());
+    }
+    Builder::start_workers(db.clone(), store.clone(), registries.clone(), options);
 
     let mut router = Router::new();
 
-    router.route(Method::Get, "/api/v1/crates/:name", api::get_crate);
-    router.route(Method::Get, "/api/v1/crates/:name/:version/status", api::get_crate_status);
+    // Every route is available for the default registry at the root and for
+    // any registry under "/registries/:registry"
+    for prefix in &["", "/registries/:registry"] {
+        let route = |path: &str| format!("{}{}", prefix, path);
 
-    router.route(Method::Get, "/", frontend::get_index);
-    router.route(Method::Get, "/crates/:name", frontend::get_crate_index);
-    router.route(Method::Get, "/crates/:name/:version", frontend::get_docs);
-    router.route(Method::Get, "/crates/:name/:version/*path", frontend::get_doc_file);
+        router.route(Method::Get, route("/api/v1/crates/:name"), api::get_crate);
+        router.route(Method::Get, route("/api/v1/crates/:name/:version/status"), api::get_crate_status);
+        router.route(Method::Get, route("/api/v1/crates/:name/:version/builds"), api::get_crate_builds);
+        router.route(Method::Get, route("/api/v1/crates/:name/:version/rustdoc.json"), api::get_rustdoc_json);
+
+        router.route(Method::Get, route("/"), frontend::get_index);
+        router.route(Method::Get, route("/crates/:name"), frontend::get_crate_index);
+        router.route(Method::Get, route("/crates/:name/:version"), frontend::get_docs);
+        router.route(Method::Get, route("/crates/:name/:version/builds"), frontend::get_builds);
+        router.route(Method::Get, route("/crates/:name/:version/*path"), frontend::get_doc_file);
+    }
 
     let mut chain = Chain::new(router);
     chain.link_before(Write::<Db>::one(db));
     chain.link_before(Read::<Store>::one(store));
-    chain.link_after(get_templates_engine());
+    chain.link_before(Read::<Registries>::one(registries));
+    chain.link_after(get_templates_engine(config));
 
     let mut mount = Mount::new();
-    mount.mount("/static/", Static::new(Path::new("public/")));
+    mount.mount("/static/", Static::new(Path::new(&config.public_path)));
     mount.mount("/", chain);
 
-    Iron::new(mount).http("localhost:3000").unwrap();
+    if let Err(err) = Iron::new(mount).http(&config.address[..]) {
+        exit_with_error(&format!("Unable to listen on {}: {}", config.address, err));
+    }
+}
+
+fn exit_with_error(message: &str) -> ! {
+    let _ = writeln!(io::stderr(), "error: {}", message);
+    process::exit(1)
+}
+
+// The crates.io registry plus any others from the config (which has already
+// validated their specs).
+fn get_registries(config: &Config) -> Registries {
+    let mut registries = Registries::new();
+    registries.add(registry::DEFAULT, Box::new(CratesIo::new(config.index.clone())));
+
+    for (name, spec) in &config.registries {
+        registries.add(name, registry::from_spec(spec).unwrap());
+    }
+
+    registries
 }
 
-fn get_templates_engine() -> HandlebarsEngine {
+fn get_templates_engine(config: &Config) -> HandlebarsEngine {
     let mut handlebars = HandlebarsEngine::new2();
-    handlebars.add(Box::new(DirectorySource::new("templates/", ".hbs")));
+    handlebars.add(Box::new(DirectorySource::new(&config.templates_path, ".hbs")));
 
     // Panic if we're unable to load all the templates
     if let Err(r) = handlebars.reload() {
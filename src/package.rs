@@ -0,0 +1,273 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use toml::{self, Value};
+
+use super::tasks::TaskError;
+
+/// The parts of an expanded crate's `Cargo.toml` that affect how its docs
+/// are built.
+#[derive(Clone, Debug)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    /// Oldest Rust version the crate supports (eg. "1.56")
+    pub rust_version: Option<String>,
+    /// Name given in the `[lib]` section, if any
+    pub lib_name: Option<String>,
+    /// Whether the package has a library, which `cargo doc` documents
+    /// instead of any binary of the same name
+    pub has_lib: bool,
+    /// Names of the package's binaries
+    pub bins: Vec<String>,
+    pub docs: DocsMetadata,
+}
+
+/// How the crate's authors want its docs built, from the
+/// `[package.metadata.docs.rs]` table used by docs.rs.
+#[derive(Clone, Debug, Default)]
+pub struct DocsMetadata {
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+    /// Target documented by default instead of the host
+    pub default_target: Option<String>,
+    /// Every target that should be documented
+    pub targets: Vec<String>,
+    pub rustdoc_args: Vec<String>,
+    pub rustc_args: Vec<String>,
+    /// Not part of docs.rs' metadata, but set when building a variant
+    pub document_private_items: bool,
+    /// Not part of docs.rs' metadata, but set by the `no-deps` fallback
+    pub no_deps: bool,
+}
+
+impl DocsMetadata {
+    fn from_table(table: Option<&Value>) -> DocsMetadata {
+        let table = match table {
+            Some(table) => table,
+            None => return DocsMetadata::default(),
+        };
+
+        DocsMetadata {
+            features: lookup_strs(table, "features"),
+            all_features: lookup_bool(table, "all-features"),
+            no_default_features: lookup_bool(table, "no-default-features"),
+            default_target: lookup_str(table, "default-target"),
+            targets: lookup_strs(table, "targets"),
+            rustdoc_args: lookup_strs(table, "rustdoc-args"),
+            rustc_args: lookup_strs(table, "rustc-args"),
+            document_private_items: false,
+            no_deps: false,
+        }
+    }
+
+    /// Splits the targets to document into the default target (`None` for
+    /// the host) and the rest. The crate's own `targets` are used if it
+    /// declares any, otherwise those `configured` for the server.
+    pub fn targets(&self, configured: &[String]) -> (Option<String>, Vec<String>) {
+        let targets = if self.targets.is_empty() {
+            configured.to_vec()
+        } else {
+            // Target names become directory names, so ignore anything odd
+            self.targets.iter().filter(|target| is_target_triple(target)).cloned().collect()
+        };
+
+        let default = match self.default_target {
+            Some(ref target) if is_target_triple(target) => Some(target.clone()),
+            _ => targets.first().cloned(),
+        };
+        let others = targets.into_iter()
+                            .filter(|target| Some(target) != default.as_ref())
+                            .collect();
+
+        (default, others)
+    }
+
+    /// Arguments for `cargo doc` selecting features and which items to
+    /// document
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = vec![];
+
+        if self.all_features {
+            args.push("--all-features".to_owned());
+        } else if !self.features.is_empty() {
+            args.push("--features".to_owned());
+            args.push(self.features.join(","));
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".to_owned());
+        }
+        if self.document_private_items {
+            args.push("--document-private-items".to_owned());
+        }
+        if self.no_deps {
+            args.push("--no-deps".to_owned());
+        }
+
+        args
+    }
+
+    /// Environment variables passing the extra rustdoc and rustc arguments
+    /// to cargo. On nightly `--cfg docsrs` is passed too, so that crates can
+    /// enable unstable features such as `doc_cfg` as they would on docs.rs.
+    pub fn env(&self, nightly: bool) -> Vec<(String, String)> {
+        let mut rustdoc_args = self.rustdoc_args.clone();
+        let mut rustc_args = self.rustc_args.clone();
+
+        if nightly {
+            rustdoc_args.extend(vec!["--cfg".to_owned(), "docsrs".to_owned()]);
+            rustc_args.extend(vec!["--cfg".to_owned(), "docsrs".to_owned()]);
+        }
+
+        let mut env = vec![];
+
+        // The encoded forms allow arguments that contain spaces
+        if !rustdoc_args.is_empty() {
+            env.push(("CARGO_ENCODED_RUSTDOCFLAGS".to_owned(), rustdoc_args.join("\x1f")));
+        }
+        if !rustc_args.is_empty() {
+            env.push(("CARGO_ENCODED_RUSTFLAGS".to_owned(), rustc_args.join("\x1f")));
+        }
+
+        env
+    }
+}
+
+impl Package {
+    /// Reads `Cargo.toml` from the root of an expanded crate.
+    pub fn read(crate_dir: &str) -> Result<Package, TaskError> {
+        let path = Path::new(crate_dir).join("Cargo.toml");
+        let mut contents = String::new();
+
+        try! {
+            File::open(&path)
+                .and_then(|mut file| file.read_to_string(&mut contents))
+                .map_err(|err| TaskError::Io(format!("Unable to read Cargo.toml: {}", err)))
+        };
+
+        let mut parser = toml::Parser::new(&contents);
+        let manifest = match parser.parse() {
+            Some(table) => Value::Table(table),
+            None => {
+                let errors: Vec<String> = parser.errors.iter().map(|err| err.desc.clone()).collect();
+                return Err(TaskError::Manifest(errors.join("\n")))
+            },
+        };
+
+        let name = match lookup_str(&manifest, "package.name") {
+            Some(name) => name,
+            None => return Err(TaskError::Manifest("package.name is missing".to_owned())),
+        };
+
+        // Libraries and binaries are either declared or found by their path
+        let root = Path::new(crate_dir);
+        let has_lib = manifest.lookup("lib").is_some() || root.join("src/lib.rs").is_file();
+
+        let mut bins: Vec<String> = manifest.lookup("bin")
+                                            .and_then(Value::as_slice)
+                                            .unwrap_or(&[])
+                                            .iter()
+                                            .filter_map(|bin| lookup_str(bin, "name"))
+                                            .collect();
+        if manifest.lookup("package.autobins").and_then(Value::as_bool) != Some(false) {
+            if root.join("src/main.rs").is_file() {
+                bins.push(name.clone());
+            }
+            bins.extend(find_bins(&root.join("src/bin")));
+        }
+        bins.sort();
+        bins.dedup();
+
+        Ok(Package {
+            name: name,
+            version: lookup_str(&manifest, "package.version").unwrap_or(String::new()),
+            rust_version: lookup_str(&manifest, "package.rust-version"),
+            lib_name: lookup_str(&manifest, "lib.name"),
+            has_lib: has_lib,
+            bins: bins,
+            // The table's name contains a dot so it can't be looked up by path
+            docs: DocsMetadata::from_table(manifest.lookup("package.metadata")
+                                                   .and_then(Value::as_table)
+                                                   .and_then(|metadata| metadata.get("docs.rs"))),
+        })
+    }
+
+    /// Name of the crate's library as rustdoc knows it, which is the
+    /// package's name with hyphens replaced unless `[lib]` renames it
+    pub fn crate_name(&self) -> String {
+        let valid = |c: char| c.is_alphanumeric() || c == '_';
+
+        match self.lib_name {
+            Some(ref name) if !name.is_empty() && name.chars().all(valid) => name.clone(),
+            _ => self.name.replace("-", "_"),
+        }
+    }
+
+    /// Names of the directories rustdoc writes the binaries' docs to
+    pub fn bin_crate_names(&self) -> Vec<String> {
+        let valid = |c: char| c.is_alphanumeric() || c == '_';
+
+        self.bins.iter()
+            .map(|bin| bin.replace("-", "_"))
+            .filter(|bin| !bin.is_empty() && bin.chars().all(valid))
+            .collect()
+    }
+}
+
+// Finds binaries in `src/bin`, either single files or directories with a
+// `main.rs`, as cargo does.
+fn find_bins(dir: &Path) -> Vec<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    entries.filter_map(|entry| entry.ok())
+           .map(|entry| entry.path())
+           .filter_map(|path| {
+               let is_bin = if path.is_dir() {
+                   path.join("main.rs").is_file()
+               } else {
+                   path.extension().map_or(false, |extension| extension == "rs")
+               };
+
+               if is_bin {
+                   path.file_stem().and_then(|stem| stem.to_str()).map(|stem| stem.to_owned())
+               } else {
+                   None
+               }
+           })
+           .collect()
+}
+
+/// Whether a name looks like a target triple (eg. "x86_64-pc-windows-msvc")
+pub fn is_target_triple(name: &str) -> bool {
+    let valid = |c: char| c.is_alphanumeric() || c == '-' || c == '_' || c == '.';
+
+    !name.is_empty() && !name.starts_with('.') && name.chars().all(valid)
+}
+
+fn lookup_str(table: &Value, path: &str) -> Option<String> {
+    table.lookup(path)
+        .and_then(Value::as_str)
+        .map(|value| value.to_owned())
+}
+
+fn lookup_bool(table: &Value, path: &str) -> bool {
+    table.lookup(path)
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+// Looks up an array of strings, ignoring anything that isn't a string.
+fn lookup_strs(table: &Value, path: &str) -> Vec<String> {
+    let values = table.lookup(path)
+                      .and_then(Value::as_slice)
+                      .unwrap_or(&[]);
+
+    values.iter()
+          .filter_map(Value::as_str)
+          .map(|value| value.to_owned())
+          .collect()
+}
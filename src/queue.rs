@@ -0,0 +1,54 @@
+use super::util::crate_pair;
+use super::variant::{self, Variant};
+
+/// A request to build a crate version's documentation. Jobs are persisted in
+/// the build queue in Redis until a worker picks them up.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct Job {
+    pub registry: String,
+    pub name: String,
+    pub version: String,
+    /// Non-default variant to build, if any
+    pub variant: Option<Variant>,
+    /// Whether to build even though docs are already stored, replacing them
+    /// if the build succeeds
+    pub rebuild: bool,
+}
+
+impl Job {
+    pub fn new(registry: &str, name: &str, version: &str) -> Job {
+        Job {
+            registry: registry.to_owned(),
+            name: name.to_owned(),
+            version: version.to_owned(),
+            variant: None,
+            rebuild: false,
+        }
+    }
+
+    pub fn with_variant(registry: &str, name: &str, version: &str, variant: Option<Variant>) -> Job {
+        Job {
+            variant: variant,
+            ..Job::new(registry, name, version)
+        }
+    }
+
+    /// Returns a job that rebuilds docs already in the store.
+    pub fn rebuild(registry: &str, name: &str, version: &str, variant: Option<Variant>) -> Job {
+        Job {
+            rebuild: true,
+            ..Job::with_variant(registry, name, version, variant)
+        }
+    }
+
+    /// Key identifying the crate version and variant (eg.
+    /// "foo-1.2.3~private-items")
+    pub fn pair(&self) -> String {
+        let pair = crate_pair(&self.registry, &self.name, &self.version);
+
+        match self.variant {
+            Some(ref variant) => format!("{}{}{}", pair, variant::SEPARATOR, variant.key()),
+            None => pair,
+        }
+    }
+}
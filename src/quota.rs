@@ -0,0 +1,143 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::config::CrateConfig;
+use super::db::Db;
+use super::queue::Job;
+use super::registry::Registries;
+use super::store::Store;
+use super::util::{compare_versions, crate_key};
+use super::variant::Variant;
+
+/// Seconds that a process may evict for before another may start, in case
+/// it dies without releasing the lock
+const EVICT_LOCK: usize = 60 * 60;
+
+/// A limit on the space the store takes up. When it's exceeded, the docs
+/// that were accessed least recently are evicted, except those of pinned
+/// crates and versions. Evicted docs are rebuilt the next time they're
+/// requested. Only one process evicts at a time, going by a lock in Redis.
+pub struct Quota {
+    /// Megabytes the store may take up
+    size: u64,
+    /// Settings for individual crates, saying which are pinned
+    crates: BTreeMap<String, CrateConfig>,
+    /// Seconds that blobs written or reused by a build are kept for, since
+    /// other processes' builds may be using them (see
+    /// `builder::Options::grace`)
+    grace: u64,
+}
+
+impl Quota {
+    pub fn new(size: u64, crates: BTreeMap<String, CrateConfig>, grace: u64) -> Quota {
+        Quota {
+            size: size,
+            crates: crates,
+            grace: grace,
+        }
+    }
+
+    /// Enforces the quota every `interval` seconds on a background thread.
+    /// Finding out how much space the store takes up means listing all of
+    /// it, so it isn't done after every build.
+    pub fn start(quota: Arc<Quota>, interval: u64, db: Arc<Mutex<Db>>, store: Arc<Store>, registries: Arc<Registries>) {
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(interval));
+
+                match quota.enforce(&db, &store, &registries) {
+                    Ok(Some((evicted, freed))) => {
+                        if !evicted.is_empty() {
+                            let _ = write!(io::stderr(), "Evicted the docs of {} crate versions ({} MB)\n", evicted.len(), freed / 1024 / 1024);
+                        }
+                    },
+                    Ok(None) => (),
+                    Err(err) => {
+                        let _ = write!(io::stderr(), "Unable to evict docs: {}\n", err);
+                    },
+                }
+            }
+        });
+    }
+
+    /// Whether a crate version's docs are never evicted.
+    pub fn is_pinned(&self, job: &Job) -> bool {
+        match self.crates.get(&crate_key(&job.registry, &job.name)) {
+            Some(krate) => {
+                krate.pinned == Some(true) ||
+                krate.pinned_versions.as_ref().map_or(false, |versions| versions.contains(&job.version))
+            },
+            None => false,
+        }
+    }
+
+    /// Evicts the least recently accessed docs until the store fits in the
+    /// quota, and returns the jobs whose docs were evicted and the bytes
+    /// freed. Docs that were never accessed go first, oldest versions first.
+    /// Returns `None` if another process is evicting.
+    pub fn enforce(&self, db: &Mutex<Db>, store: &Store, registries: &Registries) -> io::Result<Option<(Vec<Job>, u64)>> {
+        let locked = try! {
+            db.lock().unwrap().try_lock("evict", EVICT_LOCK)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err.0))
+        };
+        if !locked {
+            return Ok(None)
+        }
+
+        let result = self.evict(db, store, registries);
+        let _ = db.lock().unwrap().unlock("evict");
+
+        result.map(Some)
+    }
+
+    fn evict(&self, db: &Mutex<Db>, store: &Store, registries: &Registries) -> io::Result<(Vec<Job>, u64)> {
+        // Listing every version is slow, so only do it when it's needed
+        if try!(store.usage()) <= self.size * 1024 * 1024 {
+            return Ok((vec![], 0))
+        }
+
+        let mut candidates = vec![];
+        for registry_name in registries.names() {
+            let registry_store = store.for_registry(&registry_name);
+
+            for name in registry_store.crate_names() {
+                for version in registry_store.crate_versions(&name).unwrap_or(vec![]) {
+                    let mut variants: Vec<Option<Variant>> = vec![None];
+                    variants.extend(registry_store.crate_variants(&name, &version).into_iter().map(Some));
+
+                    for variant in variants {
+                        let job = Job::with_variant(&registry_name, &name, &version, variant);
+
+                        if !self.is_pinned(&job) {
+                            candidates.push(job);
+                        }
+                    }
+                }
+            }
+        }
+
+        let accessed = { db.lock().unwrap().get_access_times() };
+        candidates.sort_by(|a, b| {
+            let a_accessed = accessed.get(&a.pair()).cloned().unwrap_or(0);
+            let b_accessed = accessed.get(&b.pair()).cloned().unwrap_or(0);
+
+            match a_accessed.cmp(&b_accessed) {
+                Ordering::Equal => compare_versions(&a.version, &b.version),
+                ordering => ordering,
+            }
+        });
+
+        let (evicted, freed) = try!(store.evict(&candidates, self.size * 1024 * 1024, self.grace));
+
+        let db = db.lock().unwrap();
+        for job in &evicted {
+            db.forget_access(&job.pair());
+        }
+
+        Ok((evicted, freed))
+    }
+}
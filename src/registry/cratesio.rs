@@ -0,0 +1,37 @@
+use super::super::cratesio::{index, Client, Error, Metadata};
+use super::super::tasks::{TaskError, TaskOutput};
+use super::Registry;
+use super::download::download;
+
+/// The public crates.io registry.
+pub struct CratesIo {
+    /// Path to a local checkout of the index to look up checksums in instead
+    /// of the sparse index
+    index: Option<String>,
+}
+
+impl CratesIo {
+    pub fn new(index: Option<String>) -> CratesIo {
+        CratesIo {
+            index: index,
+        }
+    }
+}
+
+impl Registry for CratesIo {
+    fn get_crate(&self, name: &str) -> Result<Metadata, Error> {
+        Client::new().get_crate(name)
+    }
+
+    fn get_checksum(&self, name: &str, version: &str) -> Result<Option<String>, Error> {
+        match self.index {
+            Some(ref index) => index::get_local_checksum(index, name, version),
+            None => Client::new().get_checksum(name, version),
+        }
+    }
+
+    fn download(&self, name: &str, version: &str, dest: &str) -> Result<TaskOutput, TaskError> {
+        let url = format!("https://crates-io.s3-us-west-1.amazonaws.com/crates/{}/{}-{}.crate", name, name, version);
+        download(&url, dest)
+    }
+}
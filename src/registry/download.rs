@@ -0,0 +1,57 @@
+use hyper::client::Client;
+use hyper::status::StatusCode;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+
+use super::super::tasks::{TaskError, TaskOutput};
+
+/// Downloads a `.crate` from an HTTP(S) or `file://` URL to `dest`.
+pub fn download(url: &str, dest: &str) -> Result<TaskOutput, TaskError> {
+    if url.starts_with("file://") {
+        let source = &url["file://".len()..];
+
+        let bytes = try! {
+            fs::copy(source, dest).map_err(|err| {
+                match err.kind() {
+                    io::ErrorKind::NotFound => TaskError::NotFound,
+                    _ => TaskError::Io(format!("Unable to copy {}: {}", source, err)),
+                }
+            })
+        };
+
+        return Ok(TaskOutput::new(format!("Copied {} ({} bytes)", source, bytes)))
+    }
+
+    let client = Client::new();
+
+    let response = try! {
+        client.get(url).send()
+            .map_err(|err| TaskError::DownloadRequest(format!("{}", err)))
+    };
+
+    match response.status {
+        StatusCode::Ok => {},
+        // S3 responds with a 403 for keys that don't exist
+        StatusCode::NotFound | StatusCode::Forbidden => {
+            return Err(TaskError::NotFound)
+        },
+        status => {
+            return Err(TaskError::DownloadResponse(format!("{} responded with {}", url, status)))
+        },
+    }
+
+    let file = try! {
+        File::create(dest)
+            .map_err(|err| TaskError::DownloadResponse(format!("{}", err)))
+    };
+
+    let mut response_reader = BufReader::new(response);
+    let mut file_writer = BufWriter::new(file);
+
+    let bytes = try! {
+        io::copy(&mut response_reader, &mut file_writer)
+            .map_err(|err| TaskError::DownloadResponse(format!("{}", err)))
+    };
+
+    Ok(TaskOutput::new(format!("Downloaded {} ({} bytes)", url, bytes)))
+}
@@ -0,0 +1,148 @@
+use hyper::client::Client;
+use hyper::status::StatusCode;
+use rustc_serialize::json::Json;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use super::super::cratesio::{index, Error, Metadata};
+use super::super::tasks::{TaskError, TaskOutput};
+use super::Registry;
+use super::download::download;
+
+/// Where a registry's index lives
+pub enum IndexSource {
+    /// Base URL of a sparse HTTP index
+    Sparse(String),
+    /// Path to a local checkout of a git index
+    Local(String),
+}
+
+/// A registry described by an index and its `config.json`, such as an
+/// alternate registry or a local mirror.
+pub struct IndexRegistry {
+    source: IndexSource,
+    /// Download URL template overriding the one in `config.json`
+    dl: Option<String>,
+}
+
+impl IndexRegistry {
+    pub fn new(source: IndexSource, dl: Option<String>) -> IndexRegistry {
+        IndexRegistry {
+            source: source,
+            dl: dl,
+        }
+    }
+
+    // Reads a file from the index. Returns `None` if it doesn't exist.
+    fn read(&self, path: &str) -> Result<Option<String>, Error> {
+        let mut contents = String::new();
+
+        match self.source {
+            IndexSource::Sparse(ref url) => {
+                let url = format!("{}/{}", url.trim_right_matches('/'), path);
+
+                let mut response = try! {
+                    Client::new().get(&url).send()
+                        .map_err(|err| Error(format!("{}", err)))
+                };
+
+                match response.status {
+                    StatusCode::Ok => {},
+                    StatusCode::NotFound => return Ok(None),
+                    status => return Err(Error(format!("{} responded with {}", url, status))),
+                }
+
+                try! {
+                    response.read_to_string(&mut contents)
+                        .map_err(|err| Error(format!("{}", err)))
+                };
+            },
+            IndexSource::Local(ref index) => {
+                let path = Path::new(index).join(path);
+
+                if !path.is_file() {
+                    return Ok(None)
+                }
+
+                try! {
+                    File::open(&path)
+                        .and_then(|mut file| file.read_to_string(&mut contents))
+                        .map_err(|err| Error(format!("Unable to read {}: {}", path.display(), err)))
+                };
+            },
+        }
+
+        Ok(Some(contents))
+    }
+
+    // Download URL template, from the override or the index's `config.json`.
+    fn dl(&self) -> Result<String, Error> {
+        if let Some(ref dl) = self.dl {
+            return Ok(dl.clone())
+        }
+
+        let config = match try!(self.read("config.json")) {
+            Some(config) => config,
+            None => return Err(Error("Index has no config.json".to_owned())),
+        };
+
+        Json::from_str(&config).ok()
+            .and_then(|config| {
+                config.find("dl").and_then(|dl| dl.as_string()).map(|dl| dl.to_owned())
+            })
+            .ok_or(Error("Index's config.json has no \"dl\" URL".to_owned()))
+    }
+}
+
+impl Registry for IndexRegistry {
+    fn get_crate(&self, name: &str) -> Result<Metadata, Error> {
+        let contents = match try!(self.read(&index::index_path(name))) {
+            Some(contents) => contents,
+            None => return Err(Error(format!("Crate {} not found in index", name))),
+        };
+
+        let versions = contents.lines()
+            .filter_map(|line| Json::from_str(line).ok())
+            .filter_map(|entry| {
+                entry.find("vers").and_then(|vers| vers.as_string()).map(|vers| vers.to_owned())
+            })
+            .collect();
+
+        Ok(Metadata {
+            versions: versions,
+        })
+    }
+
+    fn get_checksum(&self, name: &str, version: &str) -> Result<Option<String>, Error> {
+        self.read(&index::index_path(name))
+            .map(|contents| {
+                contents.and_then(|contents| index::find_checksum(&contents, version))
+            })
+    }
+
+    fn download(&self, name: &str, version: &str, dest: &str) -> Result<TaskOutput, TaskError> {
+        let dl = try! {
+            self.dl().map_err(|err| TaskError::DownloadRequest(err.0))
+        };
+
+        download(&expand_dl(&dl, name, version), dest)
+    }
+}
+
+// Fills in a `config.json` download template. Templates without any markers
+// have the crate and version appended, as described by the registry spec.
+fn expand_dl(dl: &str, name: &str, version: &str) -> String {
+    let markers = ["{crate}", "{version}", "{prefix}", "{lowerprefix}"];
+
+    if !markers.iter().any(|marker| dl.contains(marker)) {
+        return format!("{}/{}/{}/download", dl.trim_right_matches('/'), name, version)
+    }
+
+    let prefix = index::prefix(name);
+
+    dl.replace("{crate}", name)
+      .replace("{version}", version)
+      .replace("{prefix}", &prefix)
+      .replace("{lowerprefix}", &prefix.to_lowercase())
+}
@@ -0,0 +1,97 @@
+use iron::prelude::Request;
+use iron::typemap;
+use persistent::Read;
+use plugin::Extensible;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::cratesio::{Error, Metadata};
+use super::tasks::{TaskError, TaskOutput};
+
+mod cratesio;
+mod download;
+mod index;
+
+pub use self::cratesio::CratesIo;
+pub use self::index::{IndexRegistry, IndexSource};
+
+/// Name of the registry used when a URL doesn't name one
+pub const DEFAULT: &'static str = "crates-io";
+
+/// Source of crate versions and `.crate` files.
+pub trait Registry: Send + Sync {
+    /// Lists the published versions of a crate.
+    fn get_crate(&self, name: &str) -> Result<Metadata, Error>;
+
+    /// Looks up the SHA-256 checksum of a version's `.crate` file. Returns
+    /// `None` if the registry doesn't know about the version.
+    fn get_checksum(&self, name: &str, version: &str) -> Result<Option<String>, Error>;
+
+    /// Downloads a version's `.crate` file to `dest`.
+    fn download(&self, name: &str, version: &str, dest: &str) -> Result<TaskOutput, TaskError>;
+}
+
+/// Creates a registry from its specification:
+///
+/// - `crates-io`: crates.io's HTTP API and sparse index
+/// - `sparse+<url>`: a sparse index described by its `config.json`
+/// - `local+<path>`: a local checkout of a git index described by its
+///   `config.json`
+///
+/// An index spec may be followed by whitespace and a download URL template
+/// (eg. `file:///srv/crates/{crate}-{version}.crate`) overriding the one in
+/// `config.json`, which allows working fully offline from a local mirror.
+pub fn from_spec(spec: &str) -> Result<Box<Registry>, Error> {
+    let mut parts = spec.split_whitespace();
+    let index = parts.next().unwrap_or("");
+    let dl = parts.next().map(|dl| dl.to_owned());
+
+    if index == DEFAULT {
+        Ok(Box::new(CratesIo::new(None)))
+    } else if index.starts_with("sparse+") {
+        let source = IndexSource::Sparse(index["sparse+".len()..].to_owned());
+        Ok(Box::new(IndexRegistry::new(source, dl)))
+    } else if index.starts_with("local+") {
+        let source = IndexSource::Local(index["local+".len()..].to_owned());
+        Ok(Box::new(IndexRegistry::new(source, dl)))
+    } else {
+        Err(Error(format!("Unrecognized registry: {}", spec)))
+    }
+}
+
+/// Registries that docs can be served for, by name.
+pub struct Registries {
+    registries: BTreeMap<String, Box<Registry>>,
+}
+
+impl Registries {
+    pub fn new() -> Registries {
+        Registries {
+            registries: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: &str, registry: Box<Registry>) {
+        self.registries.insert(name.to_owned(), registry);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Registry> {
+        self.registries.get(name).map(|registry| &**registry)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.registries.keys().cloned().collect()
+    }
+}
+
+impl typemap::Key for Registries { type Value = Registries; }
+
+pub trait GetRegistries {
+    fn get_registries(&self) -> &Arc<Registries>;
+}
+
+impl<'a, 'b> GetRegistries for Request<'a, 'b> {
+    fn get_registries<'c>(&'c self) -> &'c Arc<Registries> {
+        self.extensions().get::<Read<Registries>>().unwrap()
+    }
+}
@@ -0,0 +1,86 @@
+use std::process::Command;
+
+use super::{Sandbox, SandboxJob};
+
+/// Runs builds in a Docker or Podman container. The image must contain the
+/// build script at `/home/build-doc.sh` and rustup (see `support/Dockerfile`).
+pub struct Container {
+    /// Container runtime executable ("docker" or "podman")
+    program: String,
+    image: String,
+}
+
+impl Container {
+    pub fn new(program: &str, image: &str) -> Container {
+        Container {
+            program: program.to_owned(),
+            image: image.to_owned(),
+        }
+    }
+}
+
+impl Sandbox for Container {
+    fn describe(&self) -> String {
+        format!("{} {}", self.program, self.image)
+    }
+
+    fn command(&self, job: &SandboxJob) -> Command {
+        let mut command = Command::new(&self.program);
+        let limits = &job.limits;
+
+        command
+            .arg("run").arg("--rm")
+            .arg("--name").arg(container_name(job))
+            .arg("--volume").arg(format!("{}:/source", job.source.display()));
+
+        // The registry cache replaces the image's (see `support/Dockerfile`)
+        if let Some(ref cache) = job.cache {
+            command.arg("--volume").arg(format!("{}:/usr/local/cargo/registry:ro", cache.registry.display()));
+
+            if let Some(ref sccache) = cache.sccache {
+                command.arg("--volume").arg(format!("{}:/cache/sccache", sccache.display()));
+            }
+        }
+
+        for (key, value) in job.environment().into_iter().chain(job.sccache_environment("/cache/sccache")) {
+            command.arg("--env").arg(format!("{}={}", key, value));
+        }
+
+        if let Some(memory) = limits.memory {
+            // Setting the swap limit to the same value disables swap
+            command.arg("--memory").arg(format!("{}m", memory));
+            command.arg("--memory-swap").arg(format!("{}m", memory));
+        }
+        if let Some(cpus) = limits.cpus {
+            command.arg("--cpus").arg(cpus.to_string());
+        }
+        if let Some(processes) = limits.processes {
+            command.arg("--pids-limit").arg(processes.to_string());
+        }
+        if let Some(disk) = limits.disk {
+            command.arg("--ulimit").arg(format!("fsize={}", disk * 1024 * 1024));
+        }
+        if !limits.network() {
+            command.arg("--network").arg("none");
+        }
+
+        command
+            .arg("--workdir").arg("/source")
+            .arg(&self.image)
+            .arg("/home/build-doc.sh")
+            .args(&job.args);
+
+        command
+    }
+
+    fn kill(&self, job: &SandboxJob) {
+        // Killing the client doesn't stop the container
+        let _ = Command::new(&self.program)
+                        .arg("kill").arg(container_name(job))
+                        .output();
+    }
+}
+
+fn container_name(job: &SandboxJob) -> String {
+    format!("doc_server-{}", job.id)
+}
@@ -0,0 +1,165 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+use super::{prlimit_args, Sandbox, SandboxJob};
+
+/// Runs builds with bubblewrap, using the host's toolchain with a read-only
+/// view of the filesystem. Limits are applied with `prlimit`, so there's no
+/// CPU quota.
+pub struct Bubblewrap {
+    script: PathBuf,
+    /// The host's `CARGO_HOME`, which holds the rustup proxies
+    cargo_home: PathBuf,
+}
+
+impl Bubblewrap {
+    pub fn new(script: PathBuf, cargo_home: PathBuf) -> Bubblewrap {
+        Bubblewrap {
+            script: script,
+            cargo_home: cargo_home,
+        }
+    }
+}
+
+impl Sandbox for Bubblewrap {
+    fn describe(&self) -> String {
+        "bubblewrap".to_owned()
+    }
+
+    fn command(&self, job: &SandboxJob) -> Command {
+        let source = job.source.display().to_string();
+        let cargo_home = self.cargo_home.display().to_string();
+        let build_cargo_home = job.cargo_home.display().to_string();
+
+        let mut command = Command::new("prlimit");
+
+        command
+            .args(&prlimit_args(&job.limits))
+            .arg("--")
+            .arg("bwrap")
+            .arg("--ro-bind").arg("/").arg("/")
+            .arg("--dev").arg("/dev")
+            .arg("--proc").arg("/proc")
+            .arg("--tmpfs").arg("/tmp")
+            .arg("--bind").arg(&source).arg(&source)
+            // The build can't touch the host's cargo configuration, binaries
+            // or registry, and gets a CARGO_HOME of its own instead
+            .arg("--ro-bind").arg(&cargo_home).arg(&cargo_home)
+            .arg("--bind").arg(&build_cargo_home).arg(&build_cargo_home)
+            .arg("--unshare-all")
+            .arg("--die-with-parent")
+            .arg("--chdir").arg(&source);
+
+        let mut env = job.environment();
+        env.push(("CARGO_HOME".to_owned(), build_cargo_home.clone()));
+        if let Some(ref cache) = job.cache {
+            command.arg("--ro-bind").arg(&cache.registry).arg(format!("{}/registry", build_cargo_home));
+
+            if let Some(ref sccache) = cache.sccache {
+                command.arg("--bind").arg(sccache).arg(sccache);
+                env.extend(job.sccache_environment(&sccache.display().to_string()));
+            }
+        }
+
+        for (key, value) in env {
+            command.arg("--setenv").arg(key).arg(value);
+        }
+
+        if job.limits.network() {
+            command.arg("--share-net");
+        }
+
+        command
+            .arg(&self.script)
+            .args(&job.args);
+
+        command
+    }
+}
+
+/// Runs builds with nsjail, using the host's toolchain with a read-only view
+/// of the filesystem.
+pub struct Nsjail {
+    script: PathBuf,
+    /// The host's `CARGO_HOME`, which holds the rustup proxies
+    cargo_home: PathBuf,
+}
+
+impl Nsjail {
+    pub fn new(script: PathBuf, cargo_home: PathBuf) -> Nsjail {
+        Nsjail {
+            script: script,
+            cargo_home: cargo_home,
+        }
+    }
+}
+
+impl Sandbox for Nsjail {
+    fn describe(&self) -> String {
+        "nsjail".to_owned()
+    }
+
+    fn command(&self, job: &SandboxJob) -> Command {
+        let source = job.source.display().to_string();
+        let cargo_home = self.cargo_home.display().to_string();
+        let build_cargo_home = job.cargo_home.display().to_string();
+
+        let mut command = Command::new("nsjail");
+
+        let limits = &job.limits;
+
+        command
+            .arg("--mode").arg("o")
+            .arg("--quiet")
+            .arg("--bindmount_ro").arg("/")
+            .arg("--bindmount").arg(&source)
+            // The build can't touch the host's cargo configuration, binaries
+            // or registry, and gets a CARGO_HOME of its own instead
+            .arg("--bindmount_ro").arg(&cargo_home)
+            .arg("--bindmount").arg(&build_cargo_home)
+            .arg("--tmpfsmount").arg("/tmp")
+            .arg("--cwd").arg(&source)
+            // nsjail's own limits default to values that are too low for
+            // compiling, so always set them
+            .arg("--time_limit").arg(limits.timeout.unwrap_or(0).to_string())
+            .arg("--rlimit_as").arg(limits.memory.map(|memory| memory.to_string()).unwrap_or("inf".to_owned()))
+            .arg("--rlimit_fsize").arg(limits.disk.map(|disk| disk.to_string()).unwrap_or("inf".to_owned()))
+            .arg("--rlimit_nproc").arg(limits.processes.map(|processes| processes.to_string()).unwrap_or("inf".to_owned()));
+
+        let mut env = job.environment();
+        env.push(("CARGO_HOME".to_owned(), build_cargo_home.clone()));
+        if let Some(ref cache) = job.cache {
+            command.arg("--bindmount_ro").arg(format!("{}:{}/registry", cache.registry.display(), build_cargo_home));
+
+            if let Some(ref sccache) = cache.sccache {
+                command.arg("--bindmount").arg(sccache);
+                env.extend(job.sccache_environment(&sccache.display().to_string()));
+            }
+        }
+
+        for (key, value) in env {
+            command.arg("--env").arg(format!("{}={}", key, value));
+        }
+        if let Some(cpus) = limits.cpus {
+            command.arg("--cgroup_cpu_ms_per_sec").arg(((cpus * 1000.0) as u64).to_string());
+        }
+        if limits.network() {
+            command.arg("--disable_clone_newnet");
+        }
+
+        command
+            .arg("--")
+            .arg(&self.script)
+            .args(&job.args);
+
+        command
+    }
+}
+
+/// The host's `CARGO_HOME`
+pub fn cargo_home() -> PathBuf {
+    env::var("CARGO_HOME").map(PathBuf::from).unwrap_or_else(|_| {
+        env::home_dir().unwrap().join(".cargo")
+    })
+}
@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+use super::{prlimit_args, Sandbox, SandboxJob};
+
+/// Runs builds directly on the host without any isolation. Only for trusted
+/// crates and development. Limits are applied with `prlimit`, so there's no
+/// CPU quota and the network can't be disabled.
+pub struct Local {
+    script: PathBuf,
+}
+
+impl Local {
+    pub fn new(script: PathBuf) -> Local {
+        Local {
+            script: script,
+        }
+    }
+}
+
+impl Sandbox for Local {
+    fn describe(&self) -> String {
+        "local".to_owned()
+    }
+
+    fn command(&self, job: &SandboxJob) -> Command {
+        let mut command = Command::new("prlimit");
+
+        // Nothing stops the build writing to the cache, but it's used as
+        // the CARGO_HOME so that the registry cache is trimmed
+        let mut env = job.environment();
+        if let Some(ref cache) = job.cache {
+            env.push(("CARGO_HOME".to_owned(), cache.cargo_home.display().to_string()));
+
+            if let Some(ref sccache) = cache.sccache {
+                env.extend(job.sccache_environment(&sccache.display().to_string()));
+            }
+        }
+
+        for (key, value) in env {
+            command.env(key, value);
+        }
+
+        command
+            .current_dir(&job.source)
+            .args(&prlimit_args(&job.limits))
+            .arg("--")
+            .arg(&self.script)
+            .args(&job.args);
+
+        command
+    }
+}
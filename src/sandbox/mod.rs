@@ -0,0 +1,320 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+
+use super::cache::CacheDirs;
+
+mod container;
+mod jail;
+mod local;
+
+pub use self::container::Container;
+pub use self::jail::{cargo_home, Bubblewrap, Nsjail};
+pub use self::local::Local;
+
+/// Names of the sandboxes that can be chosen in the configuration
+pub const NAMES: &'static [&'static str] = &["docker", "podman", "bubblewrap", "nsjail", "local"];
+
+/// Resources a build may use. Unset limits aren't enforced.
+#[derive(Clone, Debug, Default, RustcDecodable)]
+pub struct Limits {
+    /// Wall-clock seconds before the build is killed
+    pub timeout: Option<u64>,
+    /// Megabytes of memory
+    pub memory: Option<u64>,
+    /// Number of CPUs (eg. 1.5)
+    pub cpus: Option<f64>,
+    /// Number of processes
+    pub processes: Option<u64>,
+    /// Megabytes that any one file written by the build may take up
+    pub disk: Option<u64>,
+    /// Bytes of each of stdout and stderr that are kept
+    pub output: Option<usize>,
+    /// Whether the build may access the network. Off by default, so builds
+    /// without a `mirror` need it turned on to download dependencies.
+    pub network: Option<bool>,
+}
+
+impl Limits {
+    /// Returns these limits with any set in `overrides` replacing them.
+    pub fn merge(&self, overrides: &Limits) -> Limits {
+        Limits {
+            timeout: overrides.timeout.or(self.timeout),
+            memory: overrides.memory.or(self.memory),
+            cpus: overrides.cpus.or(self.cpus),
+            processes: overrides.processes.or(self.processes),
+            disk: overrides.disk.or(self.disk),
+            output: overrides.output.or(self.output),
+            network: overrides.network.or(self.network),
+        }
+    }
+
+    pub fn network(&self) -> bool {
+        self.network.unwrap_or(false)
+    }
+}
+
+/// A documentation build to be run in a sandbox
+pub struct SandboxJob {
+    /// Unique ID of the build
+    pub id: String,
+    /// Absolute path to the expanded crate. Docs are generated in its
+    /// `target/doc` directory.
+    pub source: PathBuf,
+    /// Absolute path to an empty directory that sandboxes using the host's
+    /// toolchain give the build as its writable `CARGO_HOME`, since the
+    /// host's is read-only
+    pub cargo_home: PathBuf,
+    /// Extra arguments for `cargo doc`
+    pub args: Vec<String>,
+    /// Rustup toolchain to build with (eg. "stable", "nightly" or "1.56")
+    pub toolchain: String,
+    /// Extra environment variables for the build (eg. `RUSTDOCFLAGS`)
+    pub env: Vec<(String, String)>,
+    pub limits: Limits,
+    /// Shared caches to mount into the sandbox, if any
+    pub cache: Option<CacheDirs>,
+}
+
+impl SandboxJob {
+    /// Every environment variable the build script should be run with
+    pub fn environment(&self) -> Vec<(String, String)> {
+        let mut env = vec![("RUSTUP_TOOLCHAIN".to_owned(), self.toolchain.clone())];
+        env.extend(self.env.iter().cloned());
+        env
+    }
+
+    /// Environment variables that make cargo compile through sccache, given
+    /// where the sandbox mounts the job's sccache directory
+    pub fn sccache_environment(&self, dir: &str) -> Vec<(String, String)> {
+        match self.cache {
+            Some(CacheDirs { sccache: Some(_), sccache_size, .. }) => vec![
+                ("RUSTC_WRAPPER".to_owned(), "sccache".to_owned()),
+                ("SCCACHE_DIR".to_owned(), dir.to_owned()),
+                ("SCCACHE_CACHE_SIZE".to_owned(), format!("{}M", sccache_size)),
+            ],
+            _ => vec![],
+        }
+    }
+}
+
+/// Isolates the build script of the crate being documented. The build script
+/// selects the job's toolchain from the `RUSTUP_TOOLCHAIN` environment
+/// variable, installing it if needed.
+pub trait Sandbox: Send + Sync {
+    /// Describes the sandbox for build records (eg. "docker doc_server:build")
+    fn describe(&self) -> String;
+
+    /// Returns the command that runs the build script for a job in the
+    /// sandbox, enforcing the job's limits other than the timeout and output
+    /// size (which are enforced by whoever runs the command).
+    fn command(&self, job: &SandboxJob) -> Command;
+
+    /// Stops anything still running for a job after its command is killed
+    /// for timing out.
+    fn kill(&self, _job: &SandboxJob) {}
+}
+
+/// Creates the named sandbox. `image` is the container image for Docker and
+/// Podman; `script` is the path to `build-doc.sh` for the other sandboxes,
+/// which run it from the host.
+pub fn from_name(name: &str, image: &str, script: &str) -> Option<Arc<Sandbox>> {
+    let script = absolute(script);
+
+    match name {
+        "docker"     => Some(Arc::new(Container::new("docker", image))),
+        "podman"     => Some(Arc::new(Container::new("podman", image))),
+        "bubblewrap" => Some(Arc::new(Bubblewrap::new(script, cargo_home()))),
+        "nsjail"     => Some(Arc::new(Nsjail::new(script, cargo_home()))),
+        "local"      => Some(Arc::new(Local::new(script))),
+        _ => None,
+    }
+}
+
+// Arguments for `prlimit` that apply a job's memory, disk and process limits
+// to the command that follows them.
+fn prlimit_args(limits: &Limits) -> Vec<String> {
+    let mut args = vec![];
+
+    if let Some(memory) = limits.memory {
+        args.push(format!("--as={}", memory * 1024 * 1024));
+    }
+    if let Some(disk) = limits.disk {
+        args.push(format!("--fsize={}", disk * 1024 * 1024));
+    }
+    if let Some(processes) = limits.processes {
+        args.push(format!("--nproc={}", processes));
+    }
+
+    args
+}
+
+fn absolute(path: &str) -> PathBuf {
+    env::current_dir().unwrap().join(path)
+}
+
+/// Runs a shell command on the host instead of the build script, with the
+/// job's environment, so that tests can make a build do anything
+#[cfg(test)]
+pub struct Fake {
+    script: String,
+}
+
+#[cfg(test)]
+impl Fake {
+    pub fn new(script: &str) -> Fake {
+        Fake {
+            script: script.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Sandbox for Fake {
+    fn describe(&self) -> String {
+        "fake".to_owned()
+    }
+
+    fn command(&self, job: &SandboxJob) -> Command {
+        let mut command = Command::new("sh");
+
+        for (key, value) in job.environment() {
+            command.env(key, value);
+        }
+
+        command
+            .current_dir(&job.source)
+            .arg("-c").arg(&self.script)
+            .arg("sh")
+            .args(&job.args);
+
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use super::{Bubblewrap, Container, Limits, Local, Nsjail, Sandbox, SandboxJob};
+    use super::super::cache::CacheDirs;
+
+    fn job(cache: bool) -> SandboxJob {
+        SandboxJob {
+            id: "id".to_owned(),
+            source: PathBuf::from("/work/foo"),
+            cargo_home: PathBuf::from("/work/foo.cargo"),
+            args: vec!["--no-deps".to_owned()],
+            toolchain: "nightly".to_owned(),
+            env: vec![("RUSTDOCFLAGS".to_owned(), "--cfg docsrs".to_owned())],
+            limits: Limits {
+                memory: Some(512),
+                processes: Some(100),
+                network: Some(false),
+                ..Limits::default()
+            },
+            cache: if cache {
+                Some(CacheDirs {
+                    cargo_home: PathBuf::from("/cache"),
+                    registry: PathBuf::from("/cache/registry"),
+                    sccache: Some(PathBuf::from("/cache/sccache/crates-io/foo")),
+                    sccache_size: 1024,
+                })
+            } else {
+                None
+            },
+        }
+    }
+
+    // The program and arguments of a command, quoted
+    fn argv(command: &Command) -> String {
+        format!("{:?}", command)
+    }
+
+    #[test]
+    fn bubblewrap_mounts_cargo_home_read_only() {
+        let sandbox = Bubblewrap::new(PathBuf::from("/srv/build-doc.sh"), PathBuf::from("/home/docs/.cargo"));
+        let argv = argv(&sandbox.command(&job(false)));
+
+        assert!(argv.starts_with(r#""prlimit" "--as=536870912" "--nproc=100" "--" "bwrap" "--ro-bind" "/" "/""#));
+        assert!(argv.contains(r#""--bind" "/work/foo" "/work/foo""#));
+        assert!(argv.contains(r#""--ro-bind" "/home/docs/.cargo" "/home/docs/.cargo""#));
+        assert!(argv.contains(r#""--bind" "/work/foo.cargo" "/work/foo.cargo""#));
+        assert!(!argv.contains(r#""--bind" "/home/docs/.cargo""#));
+        assert!(argv.contains(r#""--setenv" "CARGO_HOME" "/work/foo.cargo""#));
+        assert!(argv.contains(r#""--setenv" "RUSTUP_TOOLCHAIN" "nightly""#));
+        assert!(argv.contains(r#""--setenv" "RUSTDOCFLAGS" "--cfg docsrs""#));
+        assert!(!argv.contains(r#""--share-net""#));
+        assert!(argv.ends_with(r#""--chdir" "/work/foo" "--setenv" "RUSTUP_TOOLCHAIN" "nightly" "--setenv" "RUSTDOCFLAGS" "--cfg docsrs" "--setenv" "CARGO_HOME" "/work/foo.cargo" "/srv/build-doc.sh" "--no-deps""#));
+    }
+
+    #[test]
+    fn bubblewrap_mounts_caches() {
+        let sandbox = Bubblewrap::new(PathBuf::from("/srv/build-doc.sh"), PathBuf::from("/home/docs/.cargo"));
+        let argv = argv(&sandbox.command(&job(true)));
+
+        assert!(argv.contains(r#""--ro-bind" "/cache/registry" "/work/foo.cargo/registry""#));
+        assert!(argv.contains(r#""--bind" "/cache/sccache/crates-io/foo" "/cache/sccache/crates-io/foo""#));
+        assert!(argv.contains(r#""--setenv" "RUSTC_WRAPPER" "sccache""#));
+        assert!(argv.contains(r#""--setenv" "SCCACHE_DIR" "/cache/sccache/crates-io/foo""#));
+    }
+
+    #[test]
+    fn nsjail_mounts_cargo_home_read_only() {
+        let sandbox = Nsjail::new(PathBuf::from("/srv/build-doc.sh"), PathBuf::from("/home/docs/.cargo"));
+        let argv = argv(&sandbox.command(&job(false)));
+
+        assert!(argv.starts_with(r#""nsjail" "--mode" "o" "--quiet" "--bindmount_ro" "/""#));
+        assert!(argv.contains(r#""--bindmount" "/work/foo""#));
+        assert!(argv.contains(r#""--bindmount_ro" "/home/docs/.cargo""#));
+        assert!(argv.contains(r#""--bindmount" "/work/foo.cargo""#));
+        assert!(!argv.contains(r#""--bindmount" "/home/docs/.cargo""#));
+        assert!(argv.contains(r#""--rlimit_as" "512" "--rlimit_fsize" "inf" "--rlimit_nproc" "100""#));
+        assert!(argv.contains(r#""--env" "CARGO_HOME=/work/foo.cargo""#));
+        assert!(argv.contains(r#""--env" "RUSTUP_TOOLCHAIN=nightly""#));
+        assert!(!argv.contains(r#""--disable_clone_newnet""#));
+        assert!(argv.ends_with(r#""--" "/srv/build-doc.sh" "--no-deps""#));
+    }
+
+    #[test]
+    fn nsjail_mounts_caches() {
+        let sandbox = Nsjail::new(PathBuf::from("/srv/build-doc.sh"), PathBuf::from("/home/docs/.cargo"));
+        let argv = argv(&sandbox.command(&job(true)));
+
+        assert!(argv.contains(r#""--bindmount_ro" "/cache/registry:/work/foo.cargo/registry""#));
+        assert!(argv.contains(r#""--bindmount" "/cache/sccache/crates-io/foo""#));
+        assert!(argv.contains(r#""--env" "SCCACHE_DIR=/cache/sccache/crates-io/foo""#));
+    }
+
+    #[test]
+    fn container_applies_limits() {
+        let sandbox = Container::new("docker", "doc_server:build");
+        let argv = argv(&sandbox.command(&job(false)));
+
+        assert!(argv.starts_with(r#""docker" "run" "--rm" "--name" "doc_server-id" "--volume" "/work/foo:/source""#));
+        assert!(argv.contains(r#""--env" "RUSTUP_TOOLCHAIN=nightly""#));
+        assert!(argv.contains(r#""--memory" "512m" "--memory-swap" "512m""#));
+        assert!(argv.contains(r#""--pids-limit" "100""#));
+        assert!(argv.contains(r#""--network" "none""#));
+        assert!(argv.ends_with(r#""--workdir" "/source" "doc_server:build" "/home/build-doc.sh" "--no-deps""#));
+    }
+
+    #[test]
+    fn container_mounts_registry_cache_read_only() {
+        let sandbox = Container::new("docker", "doc_server:build");
+        let argv = argv(&sandbox.command(&job(true)));
+
+        assert!(argv.contains(r#""--volume" "/cache/registry:/usr/local/cargo/registry:ro""#));
+        assert!(argv.contains(r#""--volume" "/cache/sccache/crates-io/foo:/cache/sccache""#));
+    }
+
+    #[test]
+    fn local_runs_script_under_prlimit() {
+        let sandbox = Local::new(PathBuf::from("/srv/build-doc.sh"));
+        let argv = argv(&sandbox.command(&job(false)));
+
+        assert!(argv.contains(r#""prlimit" "--as=536870912" "--nproc=100" "--" "/srv/build-doc.sh" "--no-deps""#));
+    }
+}
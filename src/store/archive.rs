@@ -0,0 +1,166 @@
+use rustc_serialize::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+use zstd;
+
+use super::Backend;
+use super::blobs::Blobs;
+
+/// zstd compression level of the index
+const LEVEL: i32 = 9;
+
+/// Length of the trailer holding the index's offset
+const TRAILER_LEN: u64 = 8;
+
+/// A crate version's docs stored in a single object, so that a build doesn't
+/// use up thousands of inodes (or requests). The archive holds an index of paths (eg.
+/// "foo/struct.Bar.html") to the hash of each file's contents in `Blobs`,
+/// which are shared with every other version:
+///
+/// ```text
+/// [index] [index offset: u64, little-endian]
+/// ```
+///
+/// The index is a zstd-compressed JSON object mapping paths to hashes. The
+/// trailer leaves room for other data before the index.
+pub struct Archive {
+    index: BTreeMap<String, String>,
+}
+
+impl Archive {
+    /// Reads an archive's index, returning `None` if there's no archive.
+    pub fn open(backend: &Arc<Backend>, key: &str) -> io::Result<Option<Archive>> {
+        match try!(backend.size(key)) {
+            Some(size) => Archive::open_sized(backend, key, size).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the index of an archive known to be `size` bytes long.
+    pub fn open_sized(backend: &Arc<Backend>, key: &str, size: u64) -> io::Result<Archive> {
+        if size < TRAILER_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "archive is too short"))
+        }
+
+        let end = size - TRAILER_LEN;
+        let trailer = try!(read_range(backend, key, end, TRAILER_LEN));
+        let offset = trailer.iter().rev().fold(0, |offset, byte| (offset << 8) | *byte as u64);
+
+        if offset > end {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "index offset is past the end of the archive"))
+        }
+
+        let data = try!(read_range(backend, key, offset, end - offset));
+        let data = try!(zstd::stream::decode_all(&data[..]));
+        let index = try! {
+            String::from_utf8(data).ok()
+                .and_then(|data| json::decode(&data).ok())
+                .ok_or(io::Error::new(io::ErrorKind::InvalidData, "invalid archive index"))
+        };
+
+        Ok(Archive {
+            index: index,
+        })
+    }
+
+    pub fn contains(&self, path: &str) -> bool {
+        self.index.contains_key(path)
+    }
+
+    /// Hashes of the blobs the archive's files are stored in
+    pub fn hashes(&self) -> Vec<String> {
+        self.index.values().cloned().collect()
+    }
+
+    /// Opens a file to stream its decompressed contents, returning `None`
+    /// if it isn't in the archive.
+    pub fn open(&self, blobs: &Blobs, path: &str) -> io::Result<Option<Box<Read + Send>>> {
+        match self.index.get(path) {
+            Some(hash) => blobs.open(hash),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Writes an archive, which is put in place in one go when it's finished,
+/// replacing any archive already there. The files' contents are stored in
+/// `Blobs` as they're added.
+pub struct ArchiveWriter {
+    blobs: Blobs,
+    key: String,
+    index: BTreeMap<String, String>,
+}
+
+impl ArchiveWriter {
+    pub fn new(blobs: Blobs, key: String) -> ArchiveWriter {
+        ArchiveWriter {
+            blobs: blobs,
+            key: key,
+            index: BTreeMap::new(),
+        }
+    }
+
+    /// Adds data to the archive as `path`.
+    pub fn add_data(&mut self, data: &[u8], path: &str) -> io::Result<()> {
+        let hash = try!(self.blobs.put(data));
+        self.index.insert(path.to_owned(), hash);
+
+        Ok(())
+    }
+
+    /// Adds a file to the archive as `path`.
+    pub fn add_file(&mut self, source: &Path, path: &str) -> io::Result<()> {
+        let hash = try!(self.blobs.put_file(source));
+        self.index.insert(path.to_owned(), hash);
+
+        Ok(())
+    }
+
+    /// Adds every file under a directory, with paths relative to `prefix`
+    /// (eg. "x86_64-pc-windows-msvc", or "" for the root of the archive).
+    pub fn add_dir(&mut self, source: &Path, prefix: &str) -> io::Result<()> {
+        for entry in try!(fs::read_dir(source)) {
+            let entry = try!(entry);
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+
+            // Symlinks aren't followed, so they can't point outside the docs
+            let file_type = try!(entry.file_type());
+            if file_type.is_dir() {
+                try!(self.add_dir(&entry.path(), &path));
+            } else if file_type.is_file() {
+                try!(self.add_file(&entry.path(), &path));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the index and puts the archive in place.
+    pub fn finish(self) -> io::Result<()> {
+        let index = json::encode(&self.index).unwrap();
+        let mut data = try!(zstd::stream::encode_all(index.as_bytes(), LEVEL));
+
+        // Nothing comes before the index, so it starts at 0
+        data.extend_from_slice(&[0; TRAILER_LEN as usize]);
+
+        self.blobs.backend().put(&self.key, &data)
+    }
+}
+
+// Reads a range of an archive that must exist.
+fn read_range(backend: &Arc<Backend>, key: &str, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+    match try!(backend.read_range(key, offset, length)) {
+        Some(data) => {
+            if data.len() as u64 == length {
+                Ok(data)
+            } else {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "archive is truncated"))
+            }
+        },
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "archive was removed while reading it")),
+    }
+}
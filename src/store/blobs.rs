@@ -0,0 +1,170 @@
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use zstd;
+
+use super::{Backend, BLOBS_DIR};
+use super::super::util::now;
+
+/// zstd compression level of blobs. Docs are written once and read many
+/// times, so it's worth compressing them well.
+const LEVEL: i32 = 9;
+
+/// Seconds that a blob is kept after it's written or touched on top of the
+/// grace period `remove_unused` is given. A blob this process wrote or
+/// touched within half of it is reused without touching it again, since
+/// it's kept for longer than the rest of any build using it.
+pub const TOUCH_INTERVAL: u64 = 60 * 60;
+
+/// Content-addressed storage for the files of every crate version's docs,
+/// so that files shared between versions (rustdoc's scripts, styles and
+/// fonts, and pages that didn't change between releases) are only stored
+/// once. Each file is compressed with zstd and stored under the SHA-256 hash
+/// of its contents, in a directory named after the hash's first two digits
+/// (eg. "@blobs/ab/abcdef...").
+#[derive(Clone)]
+pub struct Blobs {
+    backend: Arc<Backend>,
+    touched: Arc<Mutex<Touched>>,
+}
+
+// Blobs written or touched since `since` (in seconds since the Unix epoch)
+struct Touched {
+    since: u64,
+    hashes: HashSet<String>,
+}
+
+impl Blobs {
+    pub fn new(backend: Arc<Backend>) -> Blobs {
+        Blobs {
+            backend: backend,
+            touched: Arc::new(Mutex::new(Touched { since: now(), hashes: HashSet::new() })),
+        }
+    }
+
+    pub fn backend(&self) -> &Arc<Backend> {
+        &self.backend
+    }
+
+    /// Stores data, if it isn't already, and returns its hash. A blob
+    /// that's already stored is touched instead, so that it counts as
+    /// recently written and `remove_unused` keeps it for the build, unless
+    /// this process did so recently.
+    pub fn put(&self, data: &[u8]) -> io::Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.input(data);
+        let hash = hasher.result_str();
+
+        if self.was_touched(&hash) {
+            return Ok(hash)
+        }
+
+        let key = self.key(&hash);
+        if !try!(self.backend.touch(&key)) {
+            // Concurrent builds may write the same blob, but backends
+            // replace objects in one go so either write wins
+            let compressed = try!(zstd::stream::encode_all(data, LEVEL));
+            try!(self.backend.put(&key, &compressed));
+        }
+
+        self.touched.lock().unwrap().hashes.insert(hash.clone());
+        Ok(hash)
+    }
+
+    /// Stores the contents of a file and returns their hash.
+    pub fn put_file(&self, source: &Path) -> io::Result<String> {
+        let mut data = vec![];
+        try!(File::open(source).and_then(|mut file| file.read_to_end(&mut data)));
+
+        self.put(&data)
+    }
+
+    /// Opens a blob to read its decompressed contents, returning `None` if
+    /// there's none with the hash. Blobs are single files, so they're small
+    /// enough to decompress in one go.
+    pub fn open(&self, hash: &str) -> io::Result<Option<Box<Read + Send>>> {
+        if !is_hash(hash) {
+            return Ok(None)
+        }
+
+        match try!(self.backend.get(&self.key(hash))) {
+            Some(data) => {
+                let data = try!(zstd::stream::decode_all(&data[..]));
+                Ok(Some(Box::new(Cursor::new(data))))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Removes every blob that `is_used` says isn't used any more. Blobs
+    /// written or touched in the last `grace` seconds (plus
+    /// `TOUCH_INTERVAL`) are kept, since they may belong to a build that
+    /// hasn't finished. Returns the number of blobs removed and the bytes
+    /// they took up.
+    pub fn remove_unused<F>(&self, grace: u64, is_used: F) -> io::Result<(u64, u64)>
+        where F: Fn(&str) -> bool {
+        let (mut removed, mut bytes) = (0, 0);
+
+        for object in try!(self.backend.list_all(BLOBS_DIR)) {
+            let hash = object.key.rsplit('/').next().unwrap().to_owned();
+            if !is_hash(&hash) || is_used(&hash) {
+                continue
+            }
+
+            if try!(self.remove_if_stale(&hash, object.modified, grace)) {
+                removed += 1;
+                bytes += object.size;
+            }
+        }
+
+        Ok((removed, bytes))
+    }
+
+    /// Removes a blob that's no longer used, listed as last modified at
+    /// `modified`, unless it was written or touched in the last `grace`
+    /// seconds (plus `TOUCH_INTERVAL`). Returns whether it was removed.
+    pub fn remove_if_stale(&self, hash: &str, modified: u64, grace: u64) -> io::Result<bool> {
+        let cutoff = now().saturating_sub(grace.saturating_add(TOUCH_INTERVAL));
+        if modified > cutoff {
+            return Ok(false)
+        }
+
+        // A build may have reused the blob since it was listed
+        let key = self.key(hash);
+        match try!(self.backend.stat(&key)) {
+            Some(ref current) if current.modified <= cutoff => (),
+            _ => return Ok(false),
+        }
+
+        try!(self.backend.remove(&key));
+        Ok(true)
+    }
+
+    /// Key of the object a blob is stored in
+    pub fn key(&self, hash: &str) -> String {
+        format!("{}/{}/{}", BLOBS_DIR, &hash[..2], hash)
+    }
+
+    // Whether this process wrote or touched a blob recently enough that it
+    // needn't be touched again. What's remembered is forgotten every half
+    // of `TOUCH_INTERVAL`, so it never covers a touch older than that.
+    fn was_touched(&self, hash: &str) -> bool {
+        let mut touched = self.touched.lock().unwrap();
+
+        if now() >= touched.since + TOUCH_INTERVAL / 2 {
+            touched.since = now();
+            touched.hashes.clear();
+        }
+
+        touched.hashes.contains(hash)
+    }
+}
+
+// Whether a string is a SHA-256 hash in hex, so it's safe to use in paths.
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_digit(16))
+}
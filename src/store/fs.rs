@@ -0,0 +1,204 @@
+use libc;
+use std::ffi::CString;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::{Duration, UNIX_EPOCH};
+use uuid::Uuid;
+
+use super::{Backend, Object};
+
+/// Keeps a store's objects as files under a directory, with keys as paths
+/// relative to it.
+pub struct FileSystem {
+    root: PathBuf,
+}
+
+impl FileSystem {
+    pub fn new(root: PathBuf) -> FileSystem {
+        FileSystem {
+            root: root,
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        if key.is_empty() { self.root.clone() } else { self.root.join(key) }
+    }
+
+    // Adds every file under a directory to `objects`, with keys starting
+    // with `prefix`.
+    fn list_dir(&self, dir: &Path, prefix: &str, objects: &mut Vec<Object>) -> io::Result<()> {
+        for entry in try!(fs::read_dir(dir)) {
+            let entry = try!(entry);
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let key = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+
+            let metadata = try!(entry.metadata());
+            if metadata.is_dir() {
+                try!(self.list_dir(&entry.path(), &key, objects));
+            } else if metadata.is_file() {
+                objects.push(object(key, &metadata));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Backend for FileSystem {
+    fn open(&self, key: &str) -> io::Result<Option<Box<Read + Send>>> {
+        let path = self.path(key);
+        if !path.is_file() {
+            return Ok(None)
+        }
+
+        match File::open(path) {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_range(&self, key: &str, offset: u64, length: u64) -> io::Result<Option<Vec<u8>>> {
+        let mut file = match File::open(self.path(key)) {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut data = vec![];
+        try!(file.seek(SeekFrom::Start(offset)));
+        try!(file.take(length).read_to_end(&mut data));
+
+        Ok(Some(data))
+    }
+
+    fn size(&self, key: &str) -> io::Result<Option<u64>> {
+        match fs::metadata(self.path(key)) {
+            Ok(ref metadata) if metadata.is_file() => Ok(Some(metadata.len())),
+            Ok(_) => Ok(None),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn stat(&self, key: &str) -> io::Result<Option<Object>> {
+        match fs::metadata(self.path(key)) {
+            Ok(ref metadata) if metadata.is_file() => Ok(Some(object(key.to_owned(), metadata))),
+            Ok(_) => Ok(None),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let path = self.path(key);
+        try!(fs::create_dir_all(path.parent().unwrap()));
+
+        // Write to a unique temporary file and rename it into place, so
+        // readers and concurrent writers never see a partial file
+        let temp_path = path.with_extension(format!("{}.tmp", Uuid::new_v4().to_simple_string()));
+        let written = File::create(&temp_path).and_then(|mut file| {
+            try!(file.write_all(data));
+            file.sync_all()
+        });
+
+        match written.and_then(|_| fs::rename(&temp_path, &path)) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let _ = fs::remove_file(&temp_path);
+                Err(err)
+            },
+        }
+    }
+
+    fn touch(&self, key: &str) -> io::Result<bool> {
+        let path = try! {
+            CString::new(self.path(key).as_os_str().as_bytes())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "key contains a null byte"))
+        };
+
+        // A null time means now
+        if unsafe { libc::utimes(path.as_ptr(), ptr::null()) } == 0 {
+            return Ok(true)
+        }
+
+        match io::Error::last_os_error() {
+            ref err if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            err => Err(err),
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let path = self.path(to);
+        try!(fs::create_dir_all(path.parent().unwrap()));
+
+        fs::rename(self.path(from), path)
+    }
+
+    fn remove(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key);
+
+        let result = if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+
+        match result {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(self.path(dir)) {
+            Ok(entries) => entries,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+
+        let mut names = vec![];
+        for entry in entries {
+            let entry = try!(entry);
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            if try!(entry.metadata()).is_dir() {
+                names.push(format!("{}/", name));
+            } else {
+                names.push(name);
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn list_all(&self, dir: &str) -> io::Result<Vec<Object>> {
+        let path = self.path(dir);
+        if !path.is_dir() {
+            return Ok(vec![])
+        }
+
+        let mut objects = vec![];
+        try!(self.list_dir(&path, dir, &mut objects));
+
+        Ok(objects)
+    }
+}
+
+// Describes a file. Files are only ever replaced by renaming new ones into
+// place, so its size and modification time tell its contents apart.
+fn object(key: String, metadata: &Metadata) -> Object {
+    let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or(Duration::from_secs(0));
+
+    Object {
+        key: key,
+        size: metadata.len(),
+        modified: modified.as_secs(),
+        etag: format!("{}-{}.{:09}", metadata.len(), modified.as_secs(), modified.subsec_nanos()),
+    }
+}
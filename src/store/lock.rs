@@ -0,0 +1,138 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Keeps blobs that builds are about to use from being removed. Blobs only
+/// count as used once an archive using them is published, and builds reuse
+/// blobs that are already stored, so a blob could be removed as unused
+/// right before a build's archive starts using it. Builds hold a `Writing`
+/// from before they store their first blob until their docs are published,
+/// and removing unused blobs waits until none are held.
+#[derive(Clone)]
+pub struct BlobLock {
+    state: Arc<(Mutex<State>, Condvar)>,
+}
+
+struct State {
+    // Number of `Writing`s held
+    writers: usize,
+    // Whether blobs are being removed, or will be once the writers finish
+    removing: bool,
+}
+
+/// Held by a build while it stores and publishes its docs
+pub struct Writing {
+    lock: BlobLock,
+}
+
+/// Held while removing blobs that no archive uses
+pub struct Removing {
+    lock: BlobLock,
+}
+
+impl BlobLock {
+    pub fn new() -> BlobLock {
+        BlobLock {
+            state: Arc::new((Mutex::new(State { writers: 0, removing: false }), Condvar::new())),
+        }
+    }
+
+    /// Waits until no blobs are being removed, and returns a guard that
+    /// keeps any from being removed until it's dropped.
+    pub fn write(&self) -> Writing {
+        let &(ref state, ref changed) = &*self.state;
+        let mut state = state.lock().unwrap();
+
+        // Removing waits for the builds already writing, so new ones wait
+        // for it rather than keeping it waiting forever
+        while state.removing {
+            state = changed.wait(state).unwrap();
+        }
+        state.writers += 1;
+
+        Writing { lock: self.clone() }
+    }
+
+    /// Waits until no builds are writing blobs, and returns a guard that
+    /// keeps them from starting until it's dropped.
+    pub fn remove(&self) -> Removing {
+        let &(ref state, ref changed) = &*self.state;
+        let mut state = state.lock().unwrap();
+
+        while state.removing {
+            state = changed.wait(state).unwrap();
+        }
+        state.removing = true;
+        while state.writers > 0 {
+            state = changed.wait(state).unwrap();
+        }
+
+        Removing { lock: self.clone() }
+    }
+}
+
+impl Drop for Writing {
+    fn drop(&mut self) {
+        let &(ref state, ref changed) = &*self.lock.state;
+        state.lock().unwrap().writers -= 1;
+        changed.notify_all();
+    }
+}
+
+impl Drop for Removing {
+    fn drop(&mut self) {
+        let &(ref state, ref changed) = &*self.lock.state;
+        state.lock().unwrap().removing = false;
+        changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::BlobLock;
+
+    #[test]
+    fn removing_waits_for_writers() {
+        let lock = BlobLock::new();
+        let writing = lock.write();
+
+        let (sender, receiver) = mpsc::channel();
+        let remover = lock.clone();
+        let thread = thread::spawn(move || {
+            let _removing = remover.remove();
+            sender.send(()).unwrap();
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(receiver.try_recv().is_err());
+
+        drop(writing);
+        receiver.recv().unwrap();
+        thread.join().unwrap();
+
+        // Writers can start again once it's done
+        let _writing = lock.write();
+    }
+
+    #[test]
+    fn writers_wait_for_removing() {
+        let lock = BlobLock::new();
+        let removing = lock.remove();
+
+        let (sender, receiver) = mpsc::channel();
+        let writer = lock.clone();
+        let thread = thread::spawn(move || {
+            let _writing = writer.write();
+            sender.send(()).unwrap();
+        });
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(receiver.try_recv().is_err());
+
+        drop(removing);
+        receiver.recv().unwrap();
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,848 @@
+use iron::prelude::Request;
+use iron::typemap;
+use persistent::Read;
+use plugin::Extensible;
+use rustc_serialize::json;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read as IoRead};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use super::queue::Job;
+use super::registry;
+use super::util::now;
+use super::variant::{self, Variant};
+use self::lock::BlobLock;
+
+mod archive;
+mod blobs;
+mod fs;
+mod lock;
+mod s3;
+
+pub use self::archive::{Archive, ArchiveWriter};
+pub use self::blobs::Blobs;
+pub use self::fs::FileSystem;
+pub use self::lock::Writing;
+pub use self::s3::S3;
+
+/// Where a store keeps its objects (archives, manifests and blobs), each
+/// named by a key relative to the store's root such as "foo/1.2.3.docs" or
+/// "@blobs/ab/ab12...". Keys use "/" to separate directories.
+pub trait Backend: Send + Sync {
+    /// Opens an object to stream its contents, returning `None` if it
+    /// doesn't exist.
+    fn open(&self, key: &str) -> io::Result<Option<Box<IoRead + Send>>>;
+
+    /// Reads `length` bytes of an object starting at `offset`, returning
+    /// `None` if it doesn't exist.
+    fn read_range(&self, key: &str, offset: u64, length: u64) -> io::Result<Option<Vec<u8>>>;
+
+    /// Size in bytes of an object, or `None` if it doesn't exist
+    fn size(&self, key: &str) -> io::Result<Option<u64>>;
+
+    /// Describes an object as `list_all` would, or returns `None` if it
+    /// doesn't exist.
+    fn stat(&self, key: &str) -> io::Result<Option<Object>>;
+
+    /// Writes an object, replacing any with the same key. Readers see either
+    /// the old object or the new one, never part of it.
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Updates an object's modification time to now, returning `false` if
+    /// it doesn't exist.
+    fn touch(&self, key: &str) -> io::Result<bool>;
+
+    /// Moves an object to another key, replacing any object there.
+    fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+
+    /// Removes an object, or every object under the key as a directory.
+    fn remove(&self, key: &str) -> io::Result<()>;
+
+    /// Names of the objects and directories (ending with "/") directly in a
+    /// directory ("" for the root)
+    fn list(&self, dir: &str) -> io::Result<Vec<String>>;
+
+    /// Every object under a directory, however deep
+    fn list_all(&self, dir: &str) -> io::Result<Vec<Object>>;
+
+    /// Reads a whole object, returning `None` if it doesn't exist.
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match try!(self.open(key)) {
+            Some(mut reader) => {
+                let mut data = vec![];
+                try!(reader.read_to_end(&mut data));
+                Ok(Some(data))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// An object listed by `Backend::list_all`
+#[derive(Clone, Debug)]
+pub struct Object {
+    pub key: String,
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub modified: u64,
+    /// Changes whenever the object's contents do (eg. S3's ETag)
+    pub etag: String,
+}
+
+pub struct Store {
+    backend: Arc<Backend>,
+    // Directory of the store's crates in the backend ("" for the root)
+    prefix: String,
+    archives: ArchiveCache,
+    blobs: Blobs,
+    blob_lock: BlobLock,
+}
+
+/// Indexes and manifests of published archives, keyed by the archive's key,
+/// so that serving a file doesn't read them again. Archives don't change
+/// once they're published, but rebuilds replace them, so an entry is only
+/// used while the archive is unchanged.
+type ArchiveCache = Arc<Mutex<HashMap<String, Arc<CachedArchive>>>>;
+
+// A published archive's index and manifest, and what the archive was like
+// when they were read
+struct CachedArchive {
+    object: Object,
+    archive: Archive,
+    manifest: Manifest,
+}
+
+/// Most archives whose indexes and manifests are kept in memory
+const CACHED_ARCHIVES: usize = 1024;
+
+/// Directory under the store's root holding the stores of registries other
+/// than the default. Crate names can't start with "@" so it can't clash.
+const REGISTRIES_DIR: &'static str = "@registries";
+
+/// Directory under the store's root holding the contents of every
+/// registry's docs (see `Blobs`)
+const BLOBS_DIR: &'static str = "@blobs";
+
+/// Path of the file in a crate version's docs holding its rustdoc JSON
+pub const JSON_FILE: &'static str = "rustdoc.json";
+
+/// Extension of the archives holding crate versions' docs
+const ARCHIVE_EXTENSION: &'static str = "docs";
+
+/// Suffix of the manifests next to the archives, which mark builds as
+/// complete
+const MANIFEST_SUFFIX: &'static str = ".json";
+
+/// Suffix of the names of docs being built before they're published
+const STAGING_SUFFIX: &'static str = ".staging";
+
+/// Where a crate version's docs are stored
+#[derive(Clone)]
+pub struct StoredCrate {
+    backend: Arc<Backend>,
+    // Key of the docs without an extension (eg. "foo/1.2.3")
+    key: String,
+    archives: ArchiveCache,
+    blobs: Blobs,
+    blob_lock: BlobLock,
+}
+
+/// Metadata about how a stored crate's docs were built. It's kept in a JSON
+/// file next to the crate version's archive.
+#[derive(Clone, Debug, Default, RustcEncodable, RustcDecodable)]
+pub struct Manifest {
+    /// Verified SHA-256 checksum of the `.crate` the docs were built from
+    pub checksum: Option<String>,
+    /// Rustup toolchain the docs were built with (eg. "nightly")
+    pub toolchain: Option<String>,
+    /// Exact compiler version (eg. "rustc 1.75.0 (82e1608df 2023-12-21)")
+    pub rustc: Option<String>,
+    /// Target of the docs in the root of the crate's directory (`None` for
+    /// the build host)
+    pub default_target: Option<String>,
+    /// Other targets documented, each in a subdirectory named after it
+    pub targets: Option<Vec<String>>,
+    /// Whether rustdoc JSON was built and stored
+    pub rustdoc_json: Option<bool>,
+    /// Sandbox and image the docs were built in (eg. "docker
+    /// doc_server:build")
+    pub image: Option<String>,
+    /// Directory of the library's docs, if it has any
+    pub lib: Option<String>,
+    /// Directories of the binaries' docs (`None` for docs built before they
+    /// were recorded)
+    pub bins: Option<Vec<String>>,
+    /// Fallback the docs were built with after building them as the crate's
+    /// metadata asks failed (eg. "no-deps"), if any
+    pub fallback: Option<String>,
+}
+
+impl StoredCrate {
+    /// Key of the archive holding the crate version's docs
+    pub fn archive_key(&self) -> String {
+        format!("{}.{}", self.key, ARCHIVE_EXTENSION)
+    }
+
+    pub fn manifest_key(&self) -> String {
+        format!("{}{}", self.key, MANIFEST_SUFFIX)
+    }
+
+    /// Blobs that the contents of the crate version's docs are stored in
+    pub fn blobs(&self) -> Blobs {
+        self.blobs.clone()
+    }
+
+    /// Keeps blobs from being removed as unused until the returned guard is
+    /// dropped. Builds take it before they write the archive and hold it
+    /// until it's published, since until then it doesn't count as using the
+    /// blobs it reuses.
+    pub fn start_writing(&self) -> Writing {
+        self.blob_lock.write()
+    }
+
+    /// Starts writing the crate version's archive, which replaces any
+    /// current one when it's finished.
+    pub fn archive_writer(&self) -> ArchiveWriter {
+        ArchiveWriter::new(self.blobs(), self.archive_key())
+    }
+
+    /// Opens a file of the docs (eg. "foo/index.html") to stream its
+    /// contents, returning `None` if there's no such file.
+    pub fn open_file(&self, path: &str) -> Option<Box<IoRead + Send>> {
+        if !is_relative_path(path) {
+            return None
+        }
+
+        match self.cached() {
+            Ok(Some(cached)) => cached.archive.open(&self.blobs(), path).ok().and_then(|reader| reader),
+            // Docs stored as a directory before docs were archived
+            Ok(None) => self.backend.open(&format!("{}/{}", self.key, path)).ok().and_then(|reader| reader),
+            Err(_) => None,
+        }
+    }
+
+    pub fn has_file(&self, path: &str) -> bool {
+        if !is_relative_path(path) {
+            return false
+        }
+
+        match self.cached() {
+            Ok(Some(cached)) => cached.archive.contains(path),
+            Ok(None) => self.backend.size(&format!("{}/{}", self.key, path)).ok().and_then(|size| size).is_some(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn manifest(&self) -> Option<Manifest> {
+        match self.cached() {
+            Ok(Some(cached)) => Some(cached.manifest.clone()),
+            _ => self.read_manifest(),
+        }
+    }
+
+    pub fn write_manifest(&self, manifest: &Manifest) -> io::Result<()> {
+        let data = json::encode(manifest).unwrap();
+
+        self.backend.put(&self.manifest_key(), data.as_bytes())
+    }
+
+    /// Directory of the page that requests for the crate's docs are sent
+    /// to: the library's, or the binary's if it only has one. Returns `None`
+    /// if there's no obvious page, eg. for a crate with several binaries.
+    pub fn entry_point(&self, name: &str) -> Option<String> {
+        match self.manifest() {
+            Some(Manifest { lib: Some(lib), .. }) => Some(lib),
+            Some(Manifest { bins: Some(mut bins), .. }) => {
+                if bins.len() == 1 { bins.pop() } else { None }
+            },
+            _ => self.entry_points(name).pop(),
+        }
+    }
+
+    /// Directories of every crate documented by the build: the library's
+    /// first, and then the binaries'.
+    pub fn entry_points(&self, name: &str) -> Vec<String> {
+        match self.manifest() {
+            Some(Manifest { lib, bins: Some(bins), .. }) => lib.into_iter().chain(bins).collect(),
+            _ => {
+                // Built before entry points were recorded, so assume the
+                // usual library name
+                let lib = name.replace("-", "_");
+
+                if self.has_file(&format!("{}/index.html", lib)) {
+                    vec![lib]
+                } else {
+                    vec![]
+                }
+            },
+        }
+    }
+
+    /// Compiler version the docs were built with (eg. "1.75.0", or
+    /// "1.77.0-nightly"), if the manifest recorded it
+    pub fn rustc_version(&self) -> Option<String> {
+        self.manifest()
+            .and_then(|manifest| manifest.rustc)
+            .and_then(|rustc| rustc.split_whitespace().nth(1).map(|version| version.to_owned()))
+    }
+
+    fn read_manifest(&self) -> Option<Manifest> {
+        self.backend.get(&self.manifest_key()).ok()
+            .and_then(|data| data)
+            .and_then(|data| String::from_utf8(data).ok())
+            .and_then(|data| json::decode::<Manifest>(&data).ok())
+    }
+
+    // Returns the published archive's index and manifest, from the cache if
+    // the archive hasn't changed since they were read. `None` if there's no
+    // archive, or it isn't complete.
+    fn cached(&self) -> io::Result<Option<Arc<CachedArchive>>> {
+        let key = self.archive_key();
+        let object = match try!(self.backend.stat(&key)) {
+            Some(object) => object,
+            None => return Ok(None),
+        };
+
+        if let Some(cached) = self.archives.lock().unwrap().get(&key) {
+            let old = &cached.object;
+            if old.etag == object.etag && old.size == object.size && old.modified == object.modified {
+                return Ok(Some(cached.clone()))
+            }
+        }
+
+        // The manifest is published after the archive, so one that's older
+        // is the previous build's (or the docs are being migrated)
+        match try!(self.backend.stat(&self.manifest_key())) {
+            Some(ref manifest) if manifest.modified >= object.modified => (),
+            _ => return Ok(None),
+        }
+        let manifest = match self.read_manifest() {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
+
+        let archive = try!(Archive::open_sized(&self.backend, &key, object.size));
+        let cached = Arc::new(CachedArchive {
+            object: object,
+            archive: archive,
+            manifest: manifest,
+        });
+
+        let mut archives = self.archives.lock().unwrap();
+        if archives.len() >= CACHED_ARCHIVES {
+            // Make room by forgetting any one of them
+            if let Some(old_key) = archives.keys().next().cloned() {
+                archives.remove(&old_key);
+            }
+        }
+        archives.insert(key, cached.clone());
+
+        Ok(Some(cached))
+    }
+
+    // Whether the build finished writing the docs, which it marks by
+    // writing their manifest last
+    fn is_complete(&self) -> bool {
+        self.backend.size(&self.manifest_key()).ok().and_then(|size| size).is_some()
+    }
+
+    // Whether the docs are stored as a directory, as they were before docs
+    // were archived
+    fn is_directory(&self) -> bool {
+        self.backend.list(&self.key).map(|entries| !entries.is_empty()).unwrap_or(false)
+    }
+
+    // Returns a hidden sibling of the crate (eg. ".1.2.3.<id>.staging" for
+    // "1.2.3"). Store listings skip names starting with a dot.
+    fn sibling(&self, suffix: &str) -> StoredCrate {
+        let (parent, name) = match self.key.rfind('/') {
+            Some(index) => (&self.key[..index + 1], &self.key[index + 1..]),
+            None => ("", &self.key[..]),
+        };
+
+        StoredCrate {
+            backend: self.backend.clone(),
+            key: format!("{}.{}.{}", parent, name, suffix),
+            archives: self.archives.clone(),
+            blobs: self.blobs.clone(),
+            blob_lock: self.blob_lock.clone(),
+        }
+    }
+}
+
+// Stored crates are the same if they're stored at the same key
+impl PartialEq for StoredCrate {
+    fn eq(&self, other: &StoredCrate) -> bool {
+        self.key == other.key
+    }
+}
+impl Eq for StoredCrate {}
+impl Hash for StoredCrate {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key.hash(state)
+    }
+}
+impl fmt::Debug for StoredCrate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StoredCrate({:?})", self.key)
+    }
+}
+
+impl Store {
+    pub fn new(backend: Arc<Backend>) -> Store {
+        Store {
+            blobs: Blobs::new(backend.clone()),
+            backend: backend,
+            prefix: String::new(),
+            archives: Arc::new(Mutex::new(HashMap::new())),
+            blob_lock: BlobLock::new(),
+        }
+    }
+
+    /// Returns the store for a registry's crates. The default registry's
+    /// crates live at the root of the store. Every registry shares the root
+    /// store's blobs.
+    pub fn for_registry(&self, name: &str) -> Store {
+        let prefix = if name == registry::DEFAULT {
+            self.prefix.clone()
+        } else {
+            self.key(&format!("{}/{}", REGISTRIES_DIR, name))
+        };
+
+        Store {
+            backend: self.backend.clone(),
+            prefix: prefix,
+            archives: self.archives.clone(),
+            blobs: self.blobs.clone(),
+            blob_lock: self.blob_lock.clone(),
+        }
+    }
+
+    /// Whether a crate version's docs have been published. Builds are only
+    /// complete once their manifest is written, so docs without one (eg. left
+    /// behind by a crash, or copied by versions too old to write manifests)
+    /// aren't trusted and get rebuilt, unless `migrate` archives them first.
+    pub fn contains(&self, krate: &StoredCrate) -> bool {
+        match krate.cached() {
+            Ok(Some(_)) => true,
+            Ok(None) => krate.is_complete() && krate.is_directory(),
+            Err(_) => false,
+        }
+    }
+
+    /// Archives the docs of a crate version stored as a directory before
+    /// docs were archived, and removes the directory. Directories without a
+    /// manifest are given one. Returns `false` if there was nothing to
+    /// migrate.
+    pub fn migrate(&self, krate: &StoredCrate) -> io::Result<bool> {
+        let _writing = krate.start_writing();
+
+        if !krate.is_directory() {
+            return Ok(false)
+        }
+
+        let dir = format!("{}/", krate.key);
+        let mut writer = krate.archive_writer();
+
+        for object in try!(self.backend.list_all(&krate.key)) {
+            if let Some(data) = try!(self.backend.get(&object.key)) {
+                try!(writer.add_data(&data, &object.key[dir.len()..]));
+            }
+        }
+        try!(writer.finish());
+
+        // Docs stored by versions too old to write manifests get an empty
+        // one, so that they count as complete once they're archived
+        let manifest = krate.read_manifest().unwrap_or(Manifest::default());
+        try!(krate.write_manifest(&manifest));
+        try!(self.backend.remove(&krate.key));
+
+        Ok(true)
+    }
+
+    /// Removes the blobs that no archive in the store uses any more, except
+    /// those written in the last `grace` seconds by builds that may not have
+    /// finished. Returns the number of blobs removed and the bytes they took
+    /// up.
+    pub fn remove_unused_blobs(&self, grace: u64) -> io::Result<(u64, u64)> {
+        let _removing = self.blob_lock.remove();
+        let suffix = format!(".{}", ARCHIVE_EXTENSION);
+        let blobs_dir = format!("{}/", BLOBS_DIR);
+
+        let mut used = HashSet::new();
+        for object in try!(self.backend.list_all(&self.prefix)) {
+            if !object.key.ends_with(&suffix) || object.key.starts_with(&blobs_dir) {
+                continue
+            }
+
+            // An unreadable archive could be using any blob
+            if let Some(archive) = try!(Archive::open(&self.backend, &object.key)) {
+                used.extend(archive.hashes());
+            }
+        }
+
+        self.blobs.remove_unused(grace, |hash| used.contains(hash))
+    }
+
+    /// Bytes taken up by the store's objects
+    pub fn usage(&self) -> io::Result<u64> {
+        let objects = try!(self.backend.list_all(&self.prefix));
+
+        Ok(objects.iter().fold(0, |total, object| total + object.size))
+    }
+
+    /// Evicts crate versions' docs, in the order given, until the store
+    /// takes up no more than `size` bytes. The blobs that only the evicted
+    /// docs used go with them. Returns the jobs whose docs were evicted and
+    /// the bytes freed. Waits for this process's builds that are storing
+    /// docs to publish them first, so that blobs they use aren't counted as
+    /// unused. Other processes' builds may be reusing blobs too, so like
+    /// `remove_unused_blobs` it keeps blobs written or reused in the last
+    /// `grace` seconds.
+    pub fn evict(&self, candidates: &[Job], size: u64, grace: u64) -> io::Result<(Vec<Job>, u64)> {
+        let _removing = self.blob_lock.remove();
+        let objects = try!(self.backend.list_all(&self.prefix));
+        let mut total = objects.iter().fold(0, |total, object| total + object.size);
+
+        if total <= size {
+            return Ok((vec![], 0))
+        }
+
+        let by_key: HashMap<&str, &Object> = objects.iter().map(|object| (&object.key[..], object)).collect();
+        let suffix = format!(".{}", ARCHIVE_EXTENSION);
+        let blobs_dir = format!("{}/", BLOBS_DIR);
+
+        // Count the archives using each blob, including those of builds that
+        // are being staged
+        let mut uses: HashMap<String, u64> = HashMap::new();
+        for object in &objects {
+            if !object.key.ends_with(&suffix) || object.key.starts_with(&blobs_dir) {
+                continue
+            }
+
+            if let Some(archive) = try!(Archive::open(&self.backend, &object.key)) {
+                for hash in archive.hashes() {
+                    *uses.entry(hash).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let (mut evicted, mut freed) = (vec![], 0);
+
+        for job in candidates {
+            if total <= size {
+                break
+            }
+
+            let krate = self.for_job(job);
+            if !self.contains(&krate) {
+                continue
+            }
+
+            let hashes = match try!(Archive::open(&self.backend, &krate.archive_key())) {
+                Some(archive) => archive.hashes(),
+                None => vec![],
+            };
+
+            // The archive and manifest, or the directory the docs were
+            // stored in before they were archived
+            let dir = format!("{}/", krate.key);
+            let mut bytes = objects.iter()
+                .filter(|object| object.key == krate.archive_key() || object.key == krate.manifest_key() || object.key.starts_with(&dir))
+                .fold(0, |bytes, object| bytes + object.size);
+
+            try!(self.remove(&krate));
+
+            for hash in hashes {
+                if let Some(count) = uses.get_mut(&hash) {
+                    *count -= 1;
+
+                    if *count == 0 {
+                        // Blobs written since the store was listed are in use
+                        let key = self.blobs.key(&hash);
+                        if let Some(object) = by_key.get(&key[..]) {
+                            if try!(self.blobs.remove_if_stale(&hash, object.modified, grace)) {
+                                bytes += object.size;
+                            }
+                        }
+                    }
+                }
+            }
+
+            total = total.saturating_sub(bytes);
+            freed += bytes;
+            evicted.push(job.clone());
+        }
+
+        Ok((evicted, freed))
+    }
+
+    pub fn make_crate(&self, name: &str, version: &str) -> StoredCrate {
+        StoredCrate {
+            backend: self.backend.clone(),
+            key: self.key(&format!("{}/{}", name, version)),
+            archives: self.archives.clone(),
+            blobs: self.blobs.clone(),
+            blob_lock: self.blob_lock.clone(),
+        }
+    }
+
+    /// Returns where a variant of a crate version's docs is stored: next to
+    /// the default docs, with the variant's key appended to the version.
+    pub fn make_variant(&self, name: &str, version: &str, variant: Option<&Variant>) -> StoredCrate {
+        match variant {
+            Some(variant) => {
+                self.make_crate(name, &format!("{}{}{}", version, variant::SEPARATOR, variant.key()))
+            },
+            None => self.make_crate(name, version),
+        }
+    }
+
+    /// Returns where the docs built by a job are stored.
+    pub fn for_job(&self, job: &Job) -> StoredCrate {
+        self.for_registry(&job.registry).make_variant(&job.name, &job.version, job.variant.as_ref())
+    }
+
+    /// Returns a unique place to build a crate version's docs in before
+    /// they're published, hidden from listings of the store.
+    pub fn make_staging(&self, krate: &StoredCrate) -> StoredCrate {
+        krate.sibling(&format!("{}{}", Uuid::new_v4().to_simple_string(), STAGING_SUFFIX))
+    }
+
+    /// Publishes the docs built in `staging` as a crate version's, replacing
+    /// any it has. The archive is renamed into place before the manifest, so
+    /// readers never see a partially written build, and the build doesn't
+    /// count as complete until both are there.
+    pub fn publish(&self, staging: &StoredCrate, krate: &StoredCrate) -> io::Result<()> {
+        if staging.read_manifest().is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the build has no manifest"))
+        }
+
+        try!(self.backend.rename(&staging.archive_key(), &krate.archive_key()));
+        try!(self.backend.rename(&staging.manifest_key(), &krate.manifest_key()));
+
+        // The docs may have been stored as a directory before
+        self.backend.remove(&krate.key)
+    }
+
+    /// Removes a crate version's docs and manifest.
+    pub fn remove(&self, krate: &StoredCrate) -> io::Result<()> {
+        // The manifest goes first so that the docs stop counting as complete
+        try!(self.backend.remove(&krate.manifest_key()));
+        try!(self.backend.remove(&krate.key));
+        self.backend.remove(&krate.archive_key())
+    }
+
+    /// Removes what's left of builds that were staged more than `grace`
+    /// seconds ago but never published or cleaned up, eg. because the server
+    /// was killed. Returns the number of objects removed.
+    pub fn remove_stale_staging(&self, grace: u64) -> io::Result<u64> {
+        let cutoff = now().saturating_sub(grace);
+        let blobs_dir = format!("{}/", BLOBS_DIR);
+        let mut removed = 0;
+
+        for object in try!(self.backend.list_all(&self.prefix)) {
+            if object.modified > cutoff || object.key.starts_with(&blobs_dir) {
+                continue
+            }
+
+            // Staged docs are hidden siblings of the crate versions' such as
+            // "foo/.1.2.3.<id>.staging.docs", or files under them if they
+            // were directories
+            let staged = object.key.split('/').any(|part| part.starts_with('.') && part.contains(STAGING_SUFFIX));
+            if staged {
+                try!(self.backend.remove(&object.key));
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes every version of a crate.
+    pub fn remove_crate(&self, name: &str) -> io::Result<()> {
+        self.backend.remove(&self.key(name))
+    }
+
+    /// Returns a `Vec` of crate names (directories) in this store.
+    pub fn crate_names(&self) -> Vec<String> {
+        self.backend.list(&self.prefix).unwrap_or(vec![]).into_iter()
+            .filter(|entry| entry.ends_with('/') && !entry.starts_with('@'))
+            .map(|entry| entry.trim_right_matches('/').to_owned())
+            .collect()
+    }
+
+    /// Returns the versions of a crate in this store, not including their
+    /// variants.
+    pub fn crate_versions(&self, name: &str) -> Option<Vec<String>> {
+        let versions = self.get_stored_versions(name);
+
+        if versions.is_empty() {
+            return None
+        }
+
+        Some(versions.into_iter().filter(|version| !version.contains(variant::SEPARATOR)).collect())
+    }
+
+    /// Returns the variants of a crate version in this store.
+    pub fn crate_variants(&self, name: &str, version: &str) -> Vec<Variant> {
+        self.get_stored_versions(name).iter()
+            .filter_map(|entry| variant::split_version(entry))
+            .filter(|&(entry_version, _)| entry_version == version)
+            .filter_map(|(_, variant)| variant)
+            .collect()
+    }
+
+    /// Returns the versions (and variants) of a crate that are stored as
+    /// directories, as they were before docs were archived, whether they
+    /// have a manifest or not.
+    pub fn directory_versions(&self, name: &str) -> Vec<String> {
+        let mut versions: Vec<String> = self.backend.list(&self.key(name)).unwrap_or(vec![]).into_iter()
+            .filter(|entry| !entry.starts_with('.') && entry.ends_with('/'))
+            .map(|entry| entry.trim_right_matches('/').to_owned())
+            .collect();
+
+        versions.sort();
+        versions
+    }
+
+    // Returns the versions (and variants) of a crate whose builds are
+    // complete, ie. that have a manifest.
+    fn get_stored_versions(&self, name: &str) -> Vec<String> {
+        let mut versions: Vec<String> = vec![];
+
+        for entry in self.backend.list(&self.key(name)).unwrap_or(vec![]) {
+            // Skip builds that are being staged
+            if entry.starts_with('.') {
+                continue
+            }
+
+            if entry.ends_with(MANIFEST_SUFFIX) {
+                versions.push(entry[..entry.len() - MANIFEST_SUFFIX.len()].to_owned());
+            }
+        }
+
+        versions.sort();
+        versions
+    }
+
+    // Key of something in the store's directory
+    fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_owned()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+}
+
+// Whether a path of a file in the docs stays within them.
+fn is_relative_path(path: &str) -> bool {
+    !path.starts_with('/') && !path.split('/').any(|part| part == "..")
+}
+
+impl typemap::Key for Store { type Value = Store; }
+
+pub trait GetStore {
+    fn get_store(&self) -> &Arc<Store>;
+}
+
+impl<'a, 'b> GetStore for Request<'a, 'b> {
+    fn get_store<'c>(&'c self) -> &'c Arc<Store> {
+        self.extensions().get::<Read<Store>>().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use uuid::Uuid;
+
+    use super::{Backend, FileSystem, S3};
+
+    fn keys(mut names: Vec<String>) -> Vec<String> {
+        names.sort();
+        names
+    }
+
+    // Runs through everything a backend does, under `dir`.
+    fn round_trip(backend: &Backend, dir: &str) {
+        let key = |name: &str| format!("{}/{}", dir, name);
+
+        assert_eq!(backend.get(&key("a/b.txt")).unwrap(), None);
+        assert_eq!(backend.size(&key("a/b.txt")).unwrap(), None);
+        assert!(backend.stat(&key("a/b.txt")).unwrap().is_none());
+        assert!(!backend.touch(&key("a/b.txt")).unwrap());
+
+        backend.put(&key("a/b.txt"), b"hello world").unwrap();
+        assert_eq!(backend.get(&key("a/b.txt")).unwrap(), Some(b"hello world".to_vec()));
+        assert_eq!(backend.read_range(&key("a/b.txt"), 6, 5).unwrap(), Some(b"world".to_vec()));
+        assert_eq!(backend.read_range(&key("a/b.txt"), 6, 0).unwrap(), Some(vec![]));
+        assert_eq!(backend.size(&key("a/b.txt")).unwrap(), Some(11));
+
+        let object = backend.stat(&key("a/b.txt")).unwrap().unwrap();
+        assert_eq!(object.key, key("a/b.txt"));
+        assert_eq!(object.size, 11);
+        assert!(!object.etag.is_empty());
+
+        backend.put(&key("a/b.txt"), b"hello there!").unwrap();
+        assert_eq!(backend.get(&key("a/b.txt")).unwrap(), Some(b"hello there!".to_vec()));
+        let replaced = backend.stat(&key("a/b.txt")).unwrap().unwrap();
+        assert!(replaced.etag != object.etag);
+
+        assert!(backend.touch(&key("a/b.txt")).unwrap());
+        assert!(backend.stat(&key("a/b.txt")).unwrap().unwrap().modified >= replaced.modified);
+        assert_eq!(backend.get(&key("a/b.txt")).unwrap(), Some(b"hello there!".to_vec()));
+
+        backend.put(&key("a/c/d.txt"), b"").unwrap();
+        assert_eq!(keys(backend.list(&key("a")).unwrap()), vec!["b.txt", "c/"]);
+        assert_eq!(backend.list(dir).unwrap(), vec!["a/"]);
+        assert_eq!(keys(backend.list_all(dir).unwrap().into_iter().map(|object| object.key).collect()),
+                   vec![key("a/b.txt"), key("a/c/d.txt")]);
+        assert!(backend.list_all(&key("missing")).unwrap().is_empty());
+
+        backend.rename(&key("a/b.txt"), &key("a/e.txt")).unwrap();
+        assert_eq!(backend.get(&key("a/b.txt")).unwrap(), None);
+        assert_eq!(backend.get(&key("a/e.txt")).unwrap(), Some(b"hello there!".to_vec()));
+        assert!(backend.rename(&key("a/b.txt"), &key("a/f.txt")).is_err());
+
+        backend.remove(&key("a/c")).unwrap();
+        assert_eq!(backend.list(&key("a")).unwrap(), vec!["e.txt"]);
+        backend.remove(&key("a/e.txt")).unwrap();
+        backend.remove(&key("a/e.txt")).unwrap();
+        assert!(backend.list_all(dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn file_system_round_trip() {
+        let root = PathBuf::from(format!("tmp/store-{}", Uuid::new_v4().to_simple_string()));
+        let backend = FileSystem::new(root.clone());
+
+        round_trip(&backend, "test");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    // Only runs when DOC_SERVER_TEST_S3_ENDPOINT names an endpoint (eg.
+    // "http://localhost:9000" for MinIO), whose bucket is
+    // DOC_SERVER_TEST_S3_BUCKET and credentials are
+    // DOC_SERVER_TEST_S3_ACCESS_KEY and DOC_SERVER_TEST_S3_SECRET_KEY.
+    #[test]
+    fn s3_round_trip() {
+        let endpoint = match env::var("DOC_SERVER_TEST_S3_ENDPOINT") {
+            Ok(endpoint) => endpoint,
+            Err(_) => return,
+        };
+        let var = |name: &str| env::var(format!("DOC_SERVER_TEST_S3_{}", name)).unwrap();
+        let region = env::var("DOC_SERVER_TEST_S3_REGION").unwrap_or("us-east-1".to_owned());
+
+        let backend = S3::new(&endpoint, &var("BUCKET"), &region, "doc-server-test",
+                              &var("ACCESS_KEY"), &var("SECRET_KEY"));
+
+        round_trip(&backend, &Uuid::new_v4().to_simple_string());
+    }
+}
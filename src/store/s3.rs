@@ -0,0 +1,594 @@
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use hyper::Client;
+use hyper::client::Response;
+use hyper::header::{ContentLength, Headers};
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use rustc_serialize::hex::ToHex;
+use std::io::{self, Read};
+use std::time::Duration;
+
+use super::{Backend, Object};
+use super::super::util::now;
+
+/// Keeps a store's objects in a bucket of an S3-compatible object store
+/// (eg. Amazon S3 or MinIO), with keys prefixed by `prefix`. Buckets are
+/// addressed by path (eg. "http://localhost:9000/docs/foo/1.2.3.docs") so
+/// that any endpoint works without DNS set up for each bucket, and
+/// requests are signed with AWS Signature Version 4.
+pub struct S3 {
+    client: Client,
+    endpoint: String,
+    host: String,
+    bucket: String,
+    region: String,
+    prefix: String,
+    access_key: String,
+    secret_key: String,
+}
+
+/// SHA-256 hash of an empty payload
+const EMPTY_HASH: &'static str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Seconds to wait for a read or write on a connection before failing the
+/// request, so a stalled endpoint can't hang a build or a page forever
+const TIMEOUT: u64 = 60;
+
+impl S3 {
+    /// Creates a backend for a bucket at an endpoint (eg.
+    /// "http://localhost:9000"). `prefix` is prepended to every key, so a
+    /// bucket can be shared with other things.
+    pub fn new(endpoint: &str, bucket: &str, region: &str, prefix: &str, access_key: &str, secret_key: &str) -> S3 {
+        let endpoint = endpoint.trim_right_matches('/');
+        let host = endpoint.splitn(2, "://").nth(1).unwrap_or(endpoint).split('/').next().unwrap();
+        let prefix = prefix.trim_matches('/');
+
+        let mut client = Client::new();
+        client.set_read_timeout(Some(Duration::from_secs(TIMEOUT)));
+        client.set_write_timeout(Some(Duration::from_secs(TIMEOUT)));
+
+        S3 {
+            client: client,
+            endpoint: endpoint.to_owned(),
+            host: host.to_owned(),
+            bucket: bucket.to_owned(),
+            region: region.to_owned(),
+            prefix: if prefix.is_empty() { String::new() } else { format!("{}/", prefix) },
+            access_key: access_key.to_owned(),
+            secret_key: secret_key.to_owned(),
+        }
+    }
+
+    // Sends a signed request for an object (or the bucket, if `key` is
+    // `None`).
+    fn send(&self, method: Method, key: Option<&str>, query: &[(&str, &str)], headers: &[(&str, String)], body: &[u8]) -> io::Result<Response> {
+        let path = match key {
+            Some(key) => format!("/{}/{}", self.bucket, uri_encode(&format!("{}{}", self.prefix, key), false)),
+            None => format!("/{}", self.bucket),
+        };
+
+        let query = canonical_query(query);
+        let payload_hash = if body.is_empty() { EMPTY_HASH.to_owned() } else { sha256(body) };
+        let date = format_date(now());
+
+        let mut signed: Vec<(String, String)> = vec![
+            ("host".to_owned(), self.host.clone()),
+            ("x-amz-content-sha256".to_owned(), payload_hash.clone()),
+            ("x-amz-date".to_owned(), date.clone()),
+        ];
+        signed.extend(headers.iter().map(|&(name, ref value)| (name.to_lowercase(), value.clone())));
+        signed.sort();
+
+        let (canonical_request, signed_names) = canonical_request(&method.to_string(), &path, &query, &signed, &payload_hash);
+        let (scope, signature) = sign(&self.secret_key, &self.region, "s3", &date, &canonical_request);
+
+        let mut request_headers = Headers::new();
+        for &(ref name, ref value) in &signed {
+            request_headers.set_raw(name.clone(), vec![value.clone().into_bytes()]);
+        }
+        request_headers.set_raw("authorization", vec![
+            format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.access_key, scope, signed_names, signature).into_bytes()
+        ]);
+
+        let url = if query.is_empty() {
+            format!("{}{}", self.endpoint, path)
+        } else {
+            format!("{}{}?{}", self.endpoint, path, query)
+        };
+
+        self.client.request(method, &url[..]).headers(request_headers).body(body).send()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("S3 request for {} failed: {}", url, err)))
+    }
+
+    // Sends a request for an object, returning `None` if it doesn't exist.
+    fn send_for_object(&self, method: Method, key: &str, headers: &[(&str, String)], body: &[u8]) -> io::Result<Option<Response>> {
+        let response = try!(self.send(method.clone(), Some(key), &[], headers, body));
+
+        match response.status {
+            StatusCode::NotFound => Ok(None),
+            status if status.is_success() => Ok(Some(response)),
+            status => Err(error(&method, key, status)),
+        }
+    }
+
+    // Copies an object, returning `false` if it doesn't exist.
+    fn copy(&self, from: &str, to: &str, headers: &[(&str, String)]) -> io::Result<bool> {
+        let source = format!("/{}/{}", self.bucket, uri_encode(&format!("{}{}", self.prefix, from), false));
+        let mut headers = headers.to_vec();
+        headers.push(("x-amz-copy-source", source));
+
+        let mut response = match try!(self.send_for_object(Method::Put, to, &headers, &[])) {
+            Some(response) => response,
+            None => return Ok(false),
+        };
+
+        // Copies can fail after the response's status was sent
+        let mut xml = String::new();
+        try!(response.read_to_string(&mut xml));
+        if !tags(&xml, "Error").is_empty() {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("S3 copy of {} to {} failed: {}", from, to, xml)))
+        }
+
+        Ok(true)
+    }
+
+    // Lists objects whose keys start with `prefix` (relative to the
+    // backend's prefix), returning their keys relative to the backend's
+    // prefix and, with a `delimiter`, the "directories" they're grouped in.
+    fn list_objects(&self, prefix: &str, delimiter: Option<&str>) -> io::Result<(Vec<Object>, Vec<String>)> {
+        let prefix = format!("{}{}", self.prefix, prefix);
+        let (mut objects, mut dirs) = (vec![], vec![]);
+        let mut token: Option<String> = None;
+
+        loop {
+            let xml = {
+                let mut query = vec![("list-type", "2"), ("prefix", &prefix[..])];
+                if let Some(delimiter) = delimiter {
+                    query.push(("delimiter", delimiter));
+                }
+                if let Some(ref token) = token {
+                    query.push(("continuation-token", &token[..]));
+                }
+
+                let mut response = try!(self.send(Method::Get, None, &query, &[], &[]));
+                let mut xml = String::new();
+                try!(response.read_to_string(&mut xml));
+                if !response.status.is_success() {
+                    return Err(error(&Method::Get, &prefix, response.status))
+                }
+
+                xml
+            };
+
+            for contents in tags(&xml, "Contents") {
+                let key = tags(contents, "Key").pop().map(unescape).unwrap_or(String::new());
+                if !key.starts_with(&self.prefix) {
+                    continue
+                }
+
+                objects.push(Object {
+                    key: key[self.prefix.len()..].to_owned(),
+                    size: tags(contents, "Size").pop().and_then(|size| size.parse().ok()).unwrap_or(0),
+                    modified: tags(contents, "LastModified").pop().and_then(parse_date).unwrap_or(0),
+                    etag: tags(contents, "ETag").pop().map(unescape).unwrap_or(String::new()),
+                });
+            }
+
+            for common_prefix in tags(&xml, "CommonPrefixes") {
+                if let Some(dir) = tags(common_prefix, "Prefix").pop().map(unescape) {
+                    if dir.starts_with(&self.prefix) {
+                        dirs.push(dir[self.prefix.len()..].to_owned());
+                    }
+                }
+            }
+
+            let truncated = tags(&xml, "IsTruncated").pop() == Some("true");
+            token = tags(&xml, "NextContinuationToken").pop().map(unescape);
+            if !truncated || token.is_none() {
+                return Ok((objects, dirs))
+            }
+        }
+    }
+}
+
+impl Backend for S3 {
+    fn open(&self, key: &str) -> io::Result<Option<Box<Read + Send>>> {
+        let response = try!(self.send_for_object(Method::Get, key, &[], &[]));
+
+        Ok(response.map(|response| Box::new(response) as Box<Read + Send>))
+    }
+
+    fn read_range(&self, key: &str, offset: u64, length: u64) -> io::Result<Option<Vec<u8>>> {
+        if length == 0 {
+            return self.size(key).map(|size| size.map(|_| vec![]))
+        }
+
+        let range = format!("bytes={}-{}", offset, offset + length - 1);
+        match try!(self.send_for_object(Method::Get, key, &[("range", range)], &[])) {
+            Some(response) => {
+                let mut data = vec![];
+                try!(response.take(length).read_to_end(&mut data));
+                Ok(Some(data))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn size(&self, key: &str) -> io::Result<Option<u64>> {
+        let response = try!(self.send_for_object(Method::Head, key, &[], &[]));
+
+        Ok(response.map(|response| {
+            response.headers.get::<ContentLength>().map(|length| length.0).unwrap_or(0)
+        }))
+    }
+
+    fn stat(&self, key: &str) -> io::Result<Option<Object>> {
+        let response = try!(self.send_for_object(Method::Head, key, &[], &[]));
+
+        Ok(response.map(|response| {
+            let header = |name: &str| {
+                response.headers.get_raw(name)
+                    .and_then(|values| values.first())
+                    .map(|value| String::from_utf8_lossy(value).into_owned())
+            };
+
+            Object {
+                key: key.to_owned(),
+                size: response.headers.get::<ContentLength>().map(|length| length.0).unwrap_or(0),
+                modified: header("last-modified").as_ref().and_then(|date| parse_http_date(date)).unwrap_or(0),
+                etag: header("etag").unwrap_or(String::new()),
+            }
+        }))
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> io::Result<()> {
+        let response = try!(self.send(Method::Put, Some(key), &[], &[], data));
+
+        if response.status.is_success() {
+            Ok(())
+        } else {
+            Err(error(&Method::Put, key, response.status))
+        }
+    }
+
+    fn touch(&self, key: &str) -> io::Result<bool> {
+        // Copying an object onto itself is only allowed when replacing its
+        // metadata, and updates its modification time
+        self.copy(key, key, &[("x-amz-metadata-directive", "REPLACE".to_owned())])
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        // S3 can't rename objects, but copies replace the destination in one
+        // go so readers still never see a partial object
+        if !try!(self.copy(from, to, &[])) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't exist", from)))
+        }
+
+        self.remove(from)
+    }
+
+    fn remove(&self, key: &str) -> io::Result<()> {
+        let (objects, _) = try!(self.list_objects(&format!("{}/", key), None));
+        let keys = objects.into_iter().map(|object| object.key).chain(Some(key.to_owned()));
+
+        for key in keys {
+            try!(self.send_for_object(Method::Delete, &key, &[], &[]));
+        }
+
+        Ok(())
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+        let prefix = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+        let (objects, dirs) = try!(self.list_objects(&prefix, Some("/")));
+
+        Ok(objects.into_iter().map(|object| object.key)
+            .chain(dirs)
+            .map(|key| key[prefix.len()..].to_owned())
+            .collect())
+    }
+
+    fn list_all(&self, dir: &str) -> io::Result<Vec<Object>> {
+        let prefix = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+
+        self.list_objects(&prefix, None).map(|(objects, _)| objects)
+    }
+}
+
+fn error(method: &Method, key: &str, status: StatusCode) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("S3 {} of {} failed: {}", method, key, status))
+}
+
+// Sorts and encodes query parameters as Signature Version 4 requires.
+fn canonical_query(query: &[(&str, &str)]) -> String {
+    let mut query: Vec<String> = query.iter()
+        .map(|&(name, value)| format!("{}={}", uri_encode(name, true), uri_encode(value, true)))
+        .collect();
+    query.sort();
+    query.join("&")
+}
+
+// Returns the canonical request that Signature Version 4 signs, and the
+// names of the signed headers. `headers` must have lowercase names and be
+// sorted.
+fn canonical_request(method: &str, path: &str, query: &str, headers: &[(String, String)], payload_hash: &str) -> (String, String) {
+    let signed_names = headers.iter().map(|&(ref name, _)| &name[..]).collect::<Vec<_>>().join(";");
+    let canonical_headers: String = headers.iter()
+        .map(|&(ref name, ref value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+
+    let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}",
+                                    method, path, query, canonical_headers, signed_names, payload_hash);
+
+    (canonical_request, signed_names)
+}
+
+// Signs a canonical request sent at `date` (eg. "20240101T000000Z"),
+// returning the credential scope and the signature.
+fn sign(secret_key: &str, region: &str, service: &str, date: &str, canonical_request: &str) -> (String, String) {
+    let scope = format!("{}/{}/{}/aws4_request", &date[..8], region, service);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", date, scope, sha256(canonical_request.as_bytes()));
+
+    let mut key = hmac(format!("AWS4{}", secret_key).as_bytes(), date[..8].as_bytes());
+    for part in &[region, service, "aws4_request"] {
+        key = hmac(&key, part.as_bytes());
+    }
+
+    (scope, hmac(&key, string_to_sign.as_bytes()).to_hex())
+}
+
+fn sha256(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result_str()
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut hmac = Hmac::new(Sha256::new(), key);
+    hmac.input(data);
+    hmac.result().code().to_vec()
+}
+
+// Percent-encodes everything but unreserved characters (and slashes, unless
+// `slash` is set), as Signature Version 4 requires.
+fn uri_encode(value: &str, slash: bool) -> String {
+    let mut encoded = String::new();
+
+    for byte in value.bytes() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+// Returns the contents of every `<tag>...</tag>` in some XML, which is all
+// the parsing S3's responses need.
+fn tags<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut contents = vec![];
+    let mut rest = xml;
+
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        match rest.find(&close) {
+            Some(end) => {
+                contents.push(&rest[..end]);
+                rest = &rest[end + close.len()..];
+            },
+            None => break,
+        }
+    }
+
+    contents
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#34;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+// Formats seconds since the Unix epoch as Signature Version 4 wants (eg.
+// "20240101T000000Z").
+fn format_date(time: u64) -> String {
+    let (year, month, day) = civil_from_days((time / 86400) as i64);
+    let seconds = time % 86400;
+
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// Parses a timestamp in a listing (eg. "2024-01-01T00:00:00.000Z") into
+// seconds since the Unix epoch.
+fn parse_date(date: &str) -> Option<u64> {
+    let parts: Vec<i64> = date.split(|c| c == '-' || c == 'T' || c == ':' || c == '.' || c == 'Z')
+        .take(6)
+        .filter_map(|part| part.parse().ok())
+        .collect();
+
+    if parts.len() < 6 {
+        return None
+    }
+
+    let days = days_from_civil(parts[0], parts[1], parts[2]);
+    let time = days * 86400 + parts[3] * 3600 + parts[4] * 60 + parts[5];
+
+    if time < 0 { None } else { Some(time as u64) }
+}
+
+// Parses an HTTP date (eg. "Wed, 21 Oct 2015 07:28:00 GMT", as in a
+// Last-Modified header) into seconds since the Unix epoch.
+fn parse_http_date(date: &str) -> Option<u64> {
+    const MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    if parts.len() != 6 {
+        return None
+    }
+
+    let month = match MONTHS.iter().position(|month| *month == parts[2]) {
+        Some(month) => month as i64 + 1,
+        None => return None,
+    };
+    let (day, year) = match (parts[1].parse::<i64>(), parts[3].parse::<i64>()) {
+        (Ok(day), Ok(year)) => (day, year),
+        _ => return None,
+    };
+    let time: Vec<i64> = parts[4].split(':').filter_map(|part| part.parse().ok()).collect();
+    if time.len() != 3 {
+        return None
+    }
+
+    let time = days_from_civil(year, month, day) * 86400 + time[0] * 3600 + time[1] * 60 + time[2];
+
+    if time < 0 { None } else { Some(time as u64) }
+}
+
+// Converts days since the Unix epoch to a (year, month, day) date, using
+// Howard Hinnant's algorithm.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+
+    (year_of_era + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+// Converts a date to days since the Unix epoch (the inverse of
+// `civil_from_days`).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EMPTY_HASH, canonical_query, canonical_request, civil_from_days, days_from_civil, format_date,
+                parse_date, parse_http_date, sign, tags, unescape, uri_encode};
+
+    fn headers(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers.iter().map(|&(name, value)| (name.to_owned(), value.to_owned())).collect()
+    }
+
+    // "get-vanilla" from AWS's Signature Version 4 test suite
+    #[test]
+    fn signs_test_suite_request() {
+        let headers = headers(&[("host", "example.amazonaws.com"), ("x-amz-date", "20150830T123600Z")]);
+        let (request, signed) = canonical_request("GET", "/", "", &headers, EMPTY_HASH);
+
+        assert_eq!(request, "GET\n/\n\nhost:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\n\
+                             host;x-amz-date\n".to_owned() + EMPTY_HASH);
+        assert_eq!(signed, "host;x-amz-date");
+
+        let (scope, signature) = sign("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", "us-east-1", "service",
+                                      "20150830T123600Z", &request);
+        assert_eq!(scope, "20150830/us-east-1/service/aws4_request");
+        assert_eq!(signature, "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31");
+    }
+
+    // The "GET Object" example from S3's Signature Version 4 documentation
+    #[test]
+    fn signs_s3_get_object() {
+        let headers = headers(&[
+            ("host", "examplebucket.s3.amazonaws.com"),
+            ("range", "bytes=0-9"),
+            ("x-amz-content-sha256", EMPTY_HASH),
+            ("x-amz-date", "20130524T000000Z"),
+        ]);
+        let (request, signed) = canonical_request("GET", "/test.txt", "", &headers, EMPTY_HASH);
+        assert_eq!(signed, "host;range;x-amz-content-sha256;x-amz-date");
+
+        let (scope, signature) = sign("wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY", "us-east-1", "s3", "20130524T000000Z", &request);
+        assert_eq!(scope, "20130524/us-east-1/s3/aws4_request");
+        assert_eq!(signature, "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41");
+    }
+
+    // The "GET Bucket (List Objects)" example from S3's Signature Version 4
+    // documentation
+    #[test]
+    fn signs_s3_list_objects() {
+        let query = canonical_query(&[("prefix", "J"), ("max-keys", "2")]);
+        assert_eq!(query, "max-keys=2&prefix=J");
+
+        let headers = headers(&[
+            ("host", "examplebucket.s3.amazonaws.com"),
+            ("x-amz-content-sha256", EMPTY_HASH),
+            ("x-amz-date", "20130524T000000Z"),
+        ]);
+        let (request, _) = canonical_request("GET", "/", &query, &headers, EMPTY_HASH);
+
+        let (_, signature) = sign("wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY", "us-east-1", "s3", "20130524T000000Z", &request);
+        assert_eq!(signature, "34b48302e7b5fa45bde8084f4b7868a86f0a534bc59db6670ed5711ef69dc6f7");
+    }
+
+    #[test]
+    fn encodes_uris() {
+        assert_eq!(uri_encode("foo/1.2.3+build~x_y-z.docs", false), "foo/1.2.3%2Bbuild~x_y-z.docs");
+        assert_eq!(uri_encode("foo/bar baz", true), "foo%2Fbar%20baz");
+        assert_eq!(uri_encode("é=&", true), "%C3%A9%3D%26");
+        assert_eq!(canonical_query(&[("list-type", "2"), ("continuation-token", "a/b=")]),
+                   "continuation-token=a%2Fb%3D&list-type=2");
+    }
+
+    #[test]
+    fn converts_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19723), (2024, 1, 1));
+        for &days in &[-800000, -1, 0, 59, 60, 11016, 19723, 2932896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+
+        assert_eq!(format_date(0), "19700101T000000Z");
+        assert_eq!(format_date(1440938160), "20150830T123600Z");
+        assert_eq!(parse_date("2015-08-30T12:36:00.000Z"), Some(1440938160));
+        assert_eq!(parse_date("2015-08-30T12:36:00Z"), Some(1440938160));
+        assert_eq!(parse_date("1969-12-31T23:59:59.000Z"), None);
+        assert_eq!(parse_date("yesterday"), None);
+        assert_eq!(parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT"), Some(1445412480));
+        assert_eq!(parse_http_date("Wed, 21 Foo 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_http_date("21 Oct 2015"), None);
+    }
+
+    #[test]
+    fn scrapes_xml() {
+        let xml = "<ListBucketResult><IsTruncated>true</IsTruncated>\
+                   <Contents><Key>foo/1.2.3.docs</Key><Size>10</Size></Contents>\
+                   <Contents><Key>a&amp;b &lt;c&gt;</Key><Size>0</Size></Contents>\
+                   <NextContinuationToken>abc</NextContinuationToken>";
+
+        let contents = tags(xml, "Contents");
+        assert_eq!(contents.len(), 2);
+        assert_eq!(tags(contents[0], "Key"), vec!["foo/1.2.3.docs"]);
+        assert_eq!(unescape(tags(contents[1], "Key")[0]), "a&b <c>");
+        assert_eq!(tags(xml, "IsTruncated"), vec!["true"]);
+        assert_eq!(tags(xml, "NextContinuationToken"), vec!["abc"]);
+        assert!(tags(xml, "Missing").is_empty());
+        assert!(tags("<Key>unclosed", "Key").is_empty());
+
+        assert_eq!(unescape("&amp;lt; &quot;&#39;&#34;&apos;"), "&lt; \"'\"'");
+    }
+}
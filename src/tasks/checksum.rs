@@ -0,0 +1,50 @@
+use std::process::Command;
+
+use super::{TaskError, TaskOutput};
+use super::super::TempCrate;
+use super::super::registry::Registry;
+use super::super::util::run_command;
+
+/// Verifies the downloaded `.crate` against the checksum published in the
+/// registry index. On success the task's stdout is the verified checksum.
+pub struct ChecksumTask<'a> {
+    temp: &'a TempCrate,
+    registry: &'a Registry,
+}
+
+impl<'a> ChecksumTask<'a> {
+    pub fn new(temp: &'a TempCrate, registry: &'a Registry) -> ChecksumTask<'a> {
+        ChecksumTask {
+            temp: temp,
+            registry: registry,
+        }
+    }
+
+    pub fn run(&self) -> Result<TaskOutput, TaskError> {
+        let name = &self.temp.name;
+        let version = &self.temp.version;
+
+        let expected = match self.registry.get_checksum(name, version) {
+            Ok(Some(checksum)) => checksum,
+            Ok(None) => return Err(TaskError::NotFound),
+            Err(err) => return Err(TaskError::DownloadRequest(err.0)),
+        };
+
+        let crate_path = self.temp.crate_path.clone();
+        let sha256sum = move || {
+            Command::new("sha256sum")
+                    .arg(crate_path)
+                    .output()
+        };
+
+        let output = try!(run_command(sha256sum));
+        // Output is of the form "<checksum>  <path>"
+        let actual = output.stdout.split_whitespace().next().unwrap_or("").to_owned();
+
+        if actual == expected {
+            Ok(TaskOutput::new(actual))
+        } else {
+            Err(TaskError::ChecksumMismatch(expected, actual))
+        }
+    }
+}
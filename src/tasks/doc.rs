@@ -1,38 +1,196 @@
-use std::process::{Command, Stdio};
+use std::env;
+use std::fs;
 
-use super::TaskError;
+use super::{TaskError, TaskOutput};
 use super::super::TempCrate;
-use super::super::util::run_command;
+use super::super::cache::CacheDirs;
+use super::super::package::DocsMetadata;
+use super::super::sandbox::{Limits, Sandbox, SandboxJob};
+use super::super::util::run_limited;
 
 pub struct DocTask<'a> {
     temp: &'a TempCrate,
+    sandbox: &'a Sandbox,
+    toolchain: &'a str,
+    target: Option<&'a str>,
+    metadata: &'a DocsMetadata,
+    limits: &'a Limits,
+    cache: Option<&'a CacheDirs>,
 }
 
 impl<'a> DocTask<'a> {
-    pub fn new(temp: &'a TempCrate) -> DocTask<'a> {
+    pub fn new(temp: &'a TempCrate, sandbox: &'a Sandbox, toolchain: &'a str, target: Option<&'a str>, metadata: &'a DocsMetadata, limits: &'a Limits, cache: Option<&'a CacheDirs>) -> DocTask<'a> {
         DocTask {
             temp: temp,
+            sandbox: sandbox,
+            toolchain: toolchain,
+            target: target,
+            metadata: metadata,
+            limits: limits,
+            cache: cache,
         }
     }
 
-    pub fn run(&self) -> Result<String, TaskError> {
-        let path = self.temp.path.clone();
+    /// Builds the docs for the target (or the host) into the `TempCrate`'s
+    /// `doc_path`, as the crate's docs.rs metadata asks. The build script
+    /// prints the compiler version to stdout.
+    pub fn run(&self) -> Result<TaskOutput, TaskError> {
+        self.run_with(self.metadata.cargo_args(), vec![])
+    }
+
+    /// Builds rustdoc's JSON output of the crate's library into the
+    /// `TempCrate`'s `json_path`. It's unstable, so the toolchain has to be a
+    /// nightly.
+    pub fn run_json(&self) -> Result<TaskOutput, TaskError> {
+        // `cargo rustdoc` only documents the crate itself, so it doesn't
+        // take `--no-deps`
+        let args = self.metadata.cargo_args().into_iter().filter(|arg| arg != "--no-deps").collect();
+
+        // A separate target directory keeps the JSON out of the HTML docs
+        self.run_with(args, vec![
+            ("DOC_FORMAT".to_owned(), "json".to_owned()),
+            ("CARGO_TARGET_DIR".to_owned(), "target/json".to_owned()),
+        ])
+    }
+
+    /// Only installs the toolchain in the sandbox, if it isn't already, and
+    /// prints the version of rustc it is, so that a channel such as
+    /// "stable" can be resolved.
+    pub fn run_version(&self) -> Result<TaskOutput, TaskError> {
+        self.run_with(vec![], vec![("DOC_FORMAT".to_owned(), "version".to_owned())])
+    }
+
+    fn run_with(&self, args: Vec<String>, extra_vars: Vec<(String, String)>) -> Result<TaskOutput, TaskError> {
+        let mut vars = self.metadata.env(self.toolchain.starts_with("nightly"));
+        vars.extend(extra_vars);
+
+        // Cargo reads the target from the environment, which also tells the
+        // build script which target's standard library to install
+        if let Some(target) = self.target {
+            vars.push(("CARGO_BUILD_TARGET".to_owned(), target.to_owned()));
+        }
+
+        // The registry cache is read-only, with the dependencies already
+        // fetched into it
+        if self.cache.is_some() {
+            vars.push(("CARGO_NET_OFFLINE".to_owned(), "true".to_owned()));
+        }
+
+        let cargo_home = env::current_dir().unwrap().join(&self.temp.cargo_home);
+        try!(fs::create_dir_all(&cargo_home).map_err(|err| {
+            TaskError::Io(format!("Unable to create CARGO_HOME: {}", err))
+        }));
+
+        let job = SandboxJob {
+            id: self.temp.id.clone(),
+            source: env::current_dir().unwrap().join(&self.temp.path),
+            cargo_home: cargo_home,
+            args: args,
+            toolchain: self.toolchain.to_owned(),
+            env: vars,
+            limits: self.limits.clone(),
+            cache: self.cache.cloned(),
+        };
+
+        let command = self.sandbox.command(&job);
+        let sandbox = self.sandbox;
 
-        let command = format!("docker run -it --rm -v \"$(pwd)/{}:/source\" doc_server:build /home/build-doc.sh", &path);
+        let result = run_limited(command, self.limits.timeout, self.limits.output, || sandbox.kill(&job));
 
-        let doc = || {
-            Command::new("/bin/sh")
-                    .arg("-c")
-                    .arg(command)
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .spawn()
-                    .and_then(|c| c.wait_with_output())
+        result.map_err(|err| self.limit_error(err))
+    }
+
+    // Reports a command killed by one of our limits as exceeding it rather
+    // than as an ordinary failure.
+    fn limit_error(&self, err: TaskError) -> TaskError {
+        let limit = match err {
+            // 137 is SIGKILL (sent when out of memory) and 153 is SIGXFSZ
+            // (sent when writing past the file size limit)
+            TaskError::Command(ref status, ref stdout, ref stderr) => {
+                let output = format!("{}\n{}", stdout, stderr);
+
+                match status.code() {
+                    Some(137) if self.limits.memory.is_some() => Some(("memory", output)),
+                    Some(153) if self.limits.disk.is_some() => Some(("disk", output)),
+                    _ => None,
+                }
+            },
+            _ => None,
         };
 
-        let doc_path = &format!("{}/target/doc", self.temp.path);
+        match limit {
+            Some((limit, output)) => TaskError::LimitExceeded(limit.to_owned(), output),
+            None => err,
+        }
+    }
+}
+
+/// Compiler cache hits and misses reported by the build script, if it used
+/// sccache
+pub fn cache_stats(output: &TaskOutput) -> Option<(u64, u64)> {
+    // Lines are of the form "Cache hits    12", alongside others such as
+    // "Cache hits (Rust)    12" which are skipped since they don't parse
+    let count = |label: &str| {
+        output.stdout.lines()
+            .filter(|line| line.starts_with(label))
+            .filter_map(|line| line[label.len()..].trim().parse::<u64>().ok())
+            .next()
+    };
+
+    match (count("Cache hits"), count("Cache misses")) {
+        (Some(hits), Some(misses)) => Some((hits, misses)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{cache_stats, DocTask};
+    use super::super::{TaskError, TaskOutput};
+    use super::super::super::TempCrate;
+    use super::super::super::package::DocsMetadata;
+    use super::super::super::sandbox::{Fake, Limits};
+
+    fn run(script: &str, limits: &Limits) -> Result<TaskOutput, TaskError> {
+        let temp = TempCrate::new("crates-io", "foo", "1.0.0");
+        fs::create_dir_all(&temp.path).unwrap();
+
+        let sandbox = Fake::new(script);
+        let metadata = DocsMetadata::default();
+        let result = DocTask::new(&temp, &sandbox, "stable", None, &metadata, limits, None).run();
+
+        temp.cleanup().unwrap();
+        result
+    }
+
+    #[test]
+    fn runs_build_in_sandbox() {
+        let output = run("echo $RUSTUP_TOOLCHAIN; echo Cache hits 3; echo Cache misses 4", &Limits::default()).unwrap();
+
+        assert_eq!(output.stdout.lines().next(), Some("stable"));
+        assert_eq!(cache_stats(&output), Some((3, 4)));
+    }
+
+    #[test]
+    fn reports_killed_build_as_out_of_memory() {
+        let limits = Limits { memory: Some(512), ..Limits::default() };
 
-        run_command(doc)
-            .and_then(|_| Ok(doc_path.clone()))
+        // As a container does when the build is killed
+        match run("exit 137", &limits) {
+            Err(TaskError::LimitExceeded(ref limit, _)) => assert_eq!(limit, "memory"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn reports_timeout() {
+        let limits = Limits { timeout: Some(1), ..Limits::default() };
+
+        match run("sleep 10", &limits) {
+            Err(TaskError::Timeout(1)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
     }
 }
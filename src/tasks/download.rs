@@ -1,40 +1,21 @@
-use hyper::client::Client;
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter};
-
-use super::TaskError;
+use super::{TaskError, TaskOutput};
 use super::super::TempCrate;
+use super::super::registry::Registry;
 
 pub struct DownloadTask<'a> {
     temp: &'a TempCrate,
+    registry: &'a Registry,
 }
 
 impl<'a> DownloadTask<'a> {
-    pub fn new(temp: &'a TempCrate) -> DownloadTask<'a> {
+    pub fn new(temp: &'a TempCrate, registry: &'a Registry) -> DownloadTask<'a> {
         DownloadTask {
             temp: temp,
+            registry: registry,
         }
     }
 
-    pub fn run(&self) -> Result<(), TaskError> {
-        let client = Client::new();
-
-        let dl_url = format!("https://crates-io.s3-us-west-1.amazonaws.com/crates/{}/{}-{}.crate", self.temp.name, self.temp.name, self.temp.version);
-        let crate_path = self.temp.crate_path.clone();
-
-        let dl_response = try! {
-            client.get(&dl_url).send()
-                .map_err(|_| TaskError::DownloadRequest)
-        };
-
-        let mut response_reader = BufReader::new(dl_response);
-        let mut file_writer = BufWriter::new(File::create(crate_path).unwrap());
-
-        try! {
-            io::copy(&mut response_reader, &mut file_writer)
-                .map_err(|_| TaskError::DownloadResponse)
-        };
-
-        Ok(())
+    pub fn run(&self) -> Result<TaskOutput, TaskError> {
+        self.registry.download(&self.temp.name, &self.temp.version, &self.temp.crate_path)
     }
 }
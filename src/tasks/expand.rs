@@ -1,6 +1,6 @@
 use std::process::Command;
 
-use super::TaskError;
+use super::{TaskError, TaskOutput};
 use super::super::TempCrate;
 use super::super::util::run_command;
 
@@ -15,7 +15,7 @@ impl<'a> ExpandTask<'a> {
         }
     }
 
-    pub fn run(&self) -> Result<(), TaskError> {
+    pub fn run(&self) -> Result<TaskOutput, TaskError> {
         let crate_path = self.temp.crate_path.clone();
         let path = self.temp.path.clone();
 
@@ -0,0 +1,46 @@
+use std::process::Command;
+
+use super::{TaskError, TaskOutput};
+use super::vendor::remove_cargo_config;
+use super::super::TempCrate;
+use super::super::cache::CacheDirs;
+use super::super::sandbox::Limits;
+use super::super::util::run_limited;
+
+/// Downloads and extracts the expanded crate's dependencies into a registry
+/// cache, which sandboxes then only get read-only. Runs on the host with the
+/// build's toolchain; cargo doesn't run any of the crate's code while
+/// fetching.
+pub struct FetchTask<'a> {
+    temp: &'a TempCrate,
+    cache: &'a CacheDirs,
+    toolchain: &'a str,
+    limits: &'a Limits,
+}
+
+impl<'a> FetchTask<'a> {
+    pub fn new(temp: &'a TempCrate, cache: &'a CacheDirs, toolchain: &'a str, limits: &'a Limits) -> FetchTask<'a> {
+        FetchTask {
+            temp: temp,
+            cache: cache,
+            toolchain: toolchain,
+            limits: limits,
+        }
+    }
+
+    pub fn run(&self) -> Result<TaskOutput, TaskError> {
+        // Cargo reads configuration shipped with the crate, which mustn't
+        // get to change how the host fetches
+        try!(remove_cargo_config(&self.temp.path));
+
+        let mut command = Command::new("cargo");
+        command
+            .current_dir(&self.temp.path)
+            .env("RUSTUP_TOOLCHAIN", self.toolchain)
+            .env("CARGO_HOME", &self.cache.cargo_home)
+            .arg("fetch")
+            .arg("--quiet");
+
+        run_limited(command, self.limits.timeout, self.limits.output, || {})
+    }
+}
@@ -2,18 +2,207 @@
 
 use std::process::ExitStatus;
 
+mod checksum;
 mod doc;
 mod download;
 mod expand;
+mod fetch;
+mod vendor;
 
+pub use self::checksum::ChecksumTask;
 pub use self::download::DownloadTask;
 pub use self::expand::ExpandTask;
-pub use self::doc::DocTask;
+pub use self::fetch::FetchTask;
+pub use self::doc::{cache_stats, DocTask};
+pub use self::vendor::VendorTask;
+
+/// Output captured from a successful task
+#[derive(Clone, Debug)]
+pub struct TaskOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl TaskOutput {
+    pub fn new(stdout: String) -> TaskOutput {
+        TaskOutput {
+            stdout: stdout,
+            stderr: String::new(),
+        }
+    }
+}
+
+/// Stage of the build pipeline
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stage {
+    Download,
+    Checksum,
+    Expand,
+    Vendor,
+    Fetch,
+    Doc,
+    Store,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Stage::Download => "download",
+            Stage::Checksum => "checksum",
+            Stage::Expand   => "expand",
+            Stage::Vendor   => "vendor",
+            Stage::Fetch    => "fetch",
+            Stage::Doc      => "doc",
+            Stage::Store    => "store",
+        }
+    }
+}
+
+/// Machine-readable classification of why a task failed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Category {
+    Network,
+    /// The registry doesn't have the requested crate version
+    NotFound,
+    Compile,
+    /// Downloaded archive doesn't match the registry's checksum
+    ChecksumMismatch,
+    Timeout,
+    OutOfMemory,
+    DiskFull,
+    MissingLibrary,
+    /// Exceeded a resource limit other than memory or disk
+    LimitExceeded,
+    Unknown,
+}
+
+impl Category {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Category::Network          => "network",
+            Category::NotFound         => "not_found",
+            Category::Compile          => "compile",
+            Category::ChecksumMismatch => "checksum_mismatch",
+            Category::Timeout          => "timeout",
+            Category::OutOfMemory      => "out_of_memory",
+            Category::DiskFull         => "disk_full",
+            Category::MissingLibrary   => "missing_library",
+            Category::LimitExceeded    => "limit_exceeded",
+            Category::Unknown          => "unknown",
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub enum TaskError {
-    DownloadRequest,
-    DownloadResponse,
+    DownloadRequest(String),
+    DownloadResponse(String),
+    /// Registry responded that the crate version doesn't exist
+    NotFound,
+    /// Expected and actual SHA-256 checksums of the downloaded crate
+    ChecksumMismatch(String, String),
+    /// Filesystem error outside of a command
+    Io(String),
+    /// The crate's `Cargo.toml` couldn't be parsed
+    Manifest(String),
+    /// Killed after running for the given number of seconds
+    Timeout(u64),
+    /// Killed for exceeding a resource limit ("memory", "disk" or
+    /// "processes"); also holds the command's output
+    LimitExceeded(String, String),
     CommandExecute(String),
     Command(ExitStatus, String, String),
 }
+
+impl TaskError {
+    pub fn code(&self) -> i32 {
+        match *self {
+            TaskError::Command(ref status, _, _) => status.code().unwrap_or(-1),
+            _ => -1,
+        }
+    }
+
+    /// Human-readable description of the error
+    pub fn message(&self) -> String {
+        match *self {
+            TaskError::DownloadRequest(ref err) => format!("Unable to request crate: {}", err),
+            TaskError::DownloadResponse(ref err) => format!("Unable to download crate: {}", err),
+            TaskError::NotFound => "Crate version not found in the registry".to_owned(),
+            TaskError::ChecksumMismatch(ref expected, ref actual) => {
+                format!("Checksum mismatch: expected {} but downloaded crate has {}", expected, actual)
+            },
+            TaskError::Io(ref err) => err.clone(),
+            TaskError::Manifest(ref err) => format!("Invalid Cargo.toml: {}", err),
+            TaskError::Timeout(seconds) => format!("Build timed out after {} seconds", seconds),
+            TaskError::LimitExceeded(ref limit, ref output) => {
+                format!("Build exceeded its {} limit\n{}", limit, output).trim().to_owned()
+            },
+            TaskError::CommandExecute(ref err) => format!("Unable to execute command: {}", err),
+            TaskError::Command(_, ref stdout, ref stderr) => {
+                format!("{}\n{}", stdout, stderr).trim().to_owned()
+            },
+        }
+    }
+
+    /// Whether the error is likely to go away if the task is retried
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            // A truncated or garbled download will usually succeed next time
+            TaskError::ChecksumMismatch(_, _) => true,
+            TaskError::CommandExecute(_) => true,
+            // Docker exits with 125 when the daemon itself fails
+            TaskError::Command(ref status, _, _) if status.code() == Some(125) => true,
+            _ => self.category() == Category::Network,
+        }
+    }
+
+    pub fn category(&self) -> Category {
+        match *self {
+            TaskError::DownloadRequest(_) |
+            TaskError::DownloadResponse(_) => Category::Network,
+            TaskError::NotFound => Category::NotFound,
+            TaskError::ChecksumMismatch(_, _) => Category::ChecksumMismatch,
+            TaskError::Io(ref err) => classify_output(None, err),
+            TaskError::Manifest(_) => Category::Compile,
+            TaskError::Timeout(_) => Category::Timeout,
+            TaskError::LimitExceeded(ref limit, _) => {
+                match &limit[..] {
+                    "memory" => Category::OutOfMemory,
+                    "disk" => Category::DiskFull,
+                    _ => Category::LimitExceeded,
+                }
+            },
+            TaskError::CommandExecute(_) => Category::Unknown,
+            TaskError::Command(ref status, ref stdout, ref stderr) => {
+                classify_output(status.code(), &format!("{}\n{}", stdout, stderr))
+            },
+        }
+    }
+}
+
+// Guesses why a command failed from its exit code and output.
+fn classify_output(code: Option<i32>, output: &str) -> Category {
+    let matches = |patterns: &[&str]| {
+        patterns.iter().any(|pattern| output.contains(pattern))
+    };
+
+    if matches(&["No space left on device"]) {
+        Category::DiskFull
+    } else if code == Some(137) || matches(&["out of memory", "memory allocation of"]) {
+        // 137 is SIGKILL, which is what the kernel's OOM killer sends
+        Category::OutOfMemory
+    } else if matches(&["timed out", "Timeout"]) {
+        Category::Timeout
+    } else if matches(&["could not find system library", "pkg-config", "cannot find -l", "unable to find library"]) {
+        Category::MissingLibrary
+    } else if matches(&["no matching package named", "failed to select a version"]) {
+        // A dependency is missing from the registry or the mirror
+        Category::NotFound
+    } else if matches(&["failed to download", "failed to fetch", "Couldn't resolve host", "failed to update registry"]) {
+        Category::Network
+    } else if matches(&["could not compile", "error: aborting", "error[E"]) {
+        Category::Compile
+    } else {
+        Category::Unknown
+    }
+}
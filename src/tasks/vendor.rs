@@ -0,0 +1,133 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Command;
+
+use super::{TaskError, TaskOutput};
+use super::super::TempCrate;
+use super::super::sandbox::Limits;
+use super::super::util::run_limited;
+
+/// Resolves the expanded crate's dependencies against a local registry
+/// mirror and vendors them into its `vendor` directory, so that the docs can
+/// then be built offline. Runs on the host with the build's toolchain; cargo
+/// doesn't run any of the crate's code while vendoring.
+pub struct VendorTask<'a> {
+    temp: &'a TempCrate,
+    /// Directory of a cargo local registry (see `cargo local-registry`)
+    mirror: &'a str,
+    toolchain: &'a str,
+    limits: &'a Limits,
+}
+
+impl<'a> VendorTask<'a> {
+    pub fn new(temp: &'a TempCrate, mirror: &'a str, toolchain: &'a str, limits: &'a Limits) -> VendorTask<'a> {
+        VendorTask {
+            temp: temp,
+            mirror: mirror,
+            toolchain: toolchain,
+            limits: limits,
+        }
+    }
+
+    pub fn run(&self) -> Result<TaskOutput, TaskError> {
+        let crate_dir = Path::new(&self.temp.path);
+
+        // Resolve against the mirror instead of crates.io
+        try!(self.write_cargo_config(&format!(
+            "[source.crates-io]\n\
+             replace-with = \"mirror\"\n\n\
+             [source.mirror]\n\
+             local-registry = {:?}\n\n\
+             [net]\n\
+             offline = true\n",
+            self.mirror)));
+
+        let mut command = Command::new("cargo");
+        command
+            .current_dir(crate_dir)
+            .env("RUSTUP_TOOLCHAIN", self.toolchain)
+            .arg("vendor")
+            .arg("--quiet")
+            .arg("--offline")
+            .arg("--respect-source-config")
+            .arg("--versioned-dirs")
+            .arg("vendor");
+
+        let output = try!(run_limited(command, self.limits.timeout, self.limits.output, || {}));
+
+        // Then build from the vendored sources. Relative paths are resolved
+        // from the crate's directory, so this works inside the sandbox too.
+        try!(self.write_cargo_config(
+            "[source.crates-io]\n\
+             replace-with = \"vendored-sources\"\n\n\
+             [source.vendored-sources]\n\
+             directory = \"vendor\"\n\n\
+             [net]\n\
+             offline = true\n"));
+
+        Ok(output)
+    }
+
+    // Replaces any cargo configuration the crate was published with.
+    fn write_cargo_config(&self, config: &str) -> Result<(), TaskError> {
+        let dir = Path::new(&self.temp.path).join(".cargo");
+
+        try!(remove_cargo_config(&self.temp.path));
+
+        fs::create_dir(&dir)
+            .and_then(|_| File::create(dir.join("config.toml")))
+            .and_then(|mut file| file.write_all(config.as_bytes()))
+            .map_err(|err| TaskError::Io(format!("Unable to write cargo configuration: {}", err)))
+    }
+}
+
+/// Removes any cargo configuration an expanded crate was published with. The
+/// whole `.cargo` directory goes, since cargo would read a `config` in it in
+/// preference to a `config.toml`.
+pub fn remove_cargo_config(crate_dir: &str) -> Result<(), TaskError> {
+    let dir = Path::new(crate_dir).join(".cargo");
+
+    let result = match fs::symlink_metadata(&dir) {
+        Ok(ref metadata) if metadata.is_dir() => fs::remove_dir_all(&dir),
+        Ok(_) => fs::remove_file(&dir),
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result.map_err(|err| TaskError::Io(format!("Unable to remove cargo configuration: {}", err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::path::Path;
+
+    use super::VendorTask;
+    use super::super::super::TempCrate;
+    use super::super::super::sandbox::Limits;
+
+    #[test]
+    fn replaces_crate_cargo_config() {
+        let temp = TempCrate::new("crates-io", "foo", "1.0.0");
+        let dir = Path::new(&temp.path).join(".cargo");
+        fs::create_dir_all(&dir).unwrap();
+        File::create(dir.join("config")).unwrap().write_all(b"[build]\nrustflags = [\"--evil\"]\n").unwrap();
+
+        let limits = Limits::default();
+        let result = VendorTask::new(&temp, "mirror", "stable", &limits).write_cargo_config("[net]\noffline = true\n");
+
+        let mut config = String::new();
+        let read = File::open(dir.join("config.toml")).and_then(|mut file| file.read_to_string(&mut config));
+        let old_config = dir.join("config").exists();
+        temp.cleanup().unwrap();
+
+        assert!(result.is_ok());
+        assert!(read.is_ok());
+        assert_eq!(config, "[net]\noffline = true\n");
+        assert!(!old_config);
+    }
+}
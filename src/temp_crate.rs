@@ -2,30 +2,58 @@ use std::io;
 use std::process::{Command, Output};
 use uuid::Uuid;
 
+use super::util::crate_pair;
+
 pub struct TempCrate {
+    /// Unique ID of this build (eg. to name its container)
+    pub id: String,
+    pub registry: String,
     pub name: String,
     pub version: String,
     /// Path to the expanded crate directory
     pub path: String,
     /// Path to the downloaded crate package file
     pub crate_path: String,
+    /// Path to the writable `CARGO_HOME` of sandboxes that otherwise share
+    /// the host's
+    pub cargo_home: String,
 }
 
 impl TempCrate {
-    pub fn new(name: &str, version: &str) -> TempCrate {
-        let uuid = Uuid::new_v4();
-        let path = format!("tmp/{}-{}-{}", name, version, uuid.to_hyphenated_string());
+    pub fn new(registry: &str, name: &str, version: &str) -> TempCrate {
+        let id = Uuid::new_v4().to_hyphenated_string();
+        let path = format!("tmp/{}-{}-{}", name, version, id);
 
         TempCrate {
+            id: id,
+            registry: registry.to_owned(),
             name: name.to_owned(),
             version: version.to_owned(),
             path: path.clone(),
             crate_path: format!("{}.crate", &path),
+            cargo_home: format!("{}.cargo", &path),
         }
     }
 
     pub fn pair(&self) -> String {
-        format!("{}-{}", self.name, self.version)
+        crate_pair(&self.registry, &self.name, &self.version)
+    }
+
+    /// Path to the documentation generated by `cargo doc`, which depends on
+    /// whether it was given a `--target`
+    pub fn doc_path(&self, target: Option<&str>) -> String {
+        match target {
+            Some(target) => format!("{}/target/{}/doc", self.path, target),
+            None => format!("{}/target/doc", self.path),
+        }
+    }
+
+    /// Path to the rustdoc JSON of a library generated by `DocTask::run_json`
+    pub fn json_path(&self, target: Option<&str>, lib_name: &str) -> String {
+        match target {
+            Some(target) => format!("{}/target/json/{}/doc/{}.json", self.path, target, lib_name),
+            None => format!("{}/target/json/doc/{}.json", self.path, lib_name),
+        }
     }
 
     pub fn cleanup(&self) -> io::Result<Output> {
@@ -33,6 +61,7 @@ impl TempCrate {
                 .arg("-rf")
                 .arg(self.path.clone())
                 .arg(self.crate_path.clone())
+                .arg(self.cargo_home.clone())
                 .output()
     }
 }
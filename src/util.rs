@@ -1,12 +1,20 @@
 #![allow(dead_code)]
 
-use std::io;
+use std::cmp::{self, Ordering};
+use std::io::{self, Read};
 use std::ops::FnOnce;
-use std::process::Output;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use libc;
+use wait_timeout::ChildExt;
 
-use super::tasks::TaskError;
+use super::registry;
+use super::tasks::{TaskError, TaskOutput};
 
-pub fn run_command<F>(command: F) -> Result<(), TaskError>
+pub fn run_command<F>(command: F) -> Result<TaskOutput, TaskError>
     where F: FnOnce() -> io::Result<Output> {
     let output = command();
 
@@ -15,13 +23,203 @@ pub fn run_command<F>(command: F) -> Result<(), TaskError>
             TaskError::CommandExecute(format!("{}", err))
         })
         .and_then(|output| {
+            let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
             if output.status.success() {
-                Ok(())
+                Ok(TaskOutput {
+                    stdout: stdout,
+                    stderr: stderr,
+                })
             } else {
-                let status = output.status;
-                let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
-                let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
-                Err(TaskError::Command(status, stdout, stderr))
+                Err(TaskError::Command(output.status, stdout, stderr))
             }
         })
 }
+
+/// Seconds to wait for a command's output after it exits. Anything it left
+/// running in the background could otherwise keep its pipes open forever.
+const OUTPUT_WAIT: u64 = 10;
+
+/// Runs a command in its own process group, killing the whole group if it's
+/// still running after `timeout` seconds (calling `on_timeout` first to clean
+/// up anything it started outside of the group) and keeping at most
+/// `output_limit` bytes of each of its stdout and stderr.
+pub fn run_limited<F>(mut command: Command, timeout: Option<u64>, output_limit: Option<usize>, on_timeout: F) -> Result<TaskOutput, TaskError>
+    where F: FnOnce() {
+    let mut child = try! {
+        command
+            .process_group(0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| TaskError::CommandExecute(format!("{}", err)))
+    };
+
+    // Read the output on other threads so that the child can't block on a
+    // full pipe while we wait for it
+    let stdout = read_limited(child.stdout.take().unwrap(), output_limit);
+    let stderr = read_limited(child.stderr.take().unwrap(), output_limit);
+
+    let wait = match timeout {
+        Some(seconds) => child.wait_timeout(Duration::from_secs(seconds)),
+        None => child.wait().map(Some),
+    };
+
+    let status = match wait {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            on_timeout();
+
+            // The child leads its process group, so the group's ID is its PID
+            unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL); }
+            let _ = child.kill();
+            let _ = child.wait();
+
+            return Err(TaskError::Timeout(timeout.unwrap()))
+        },
+        Err(err) => return Err(TaskError::CommandExecute(format!("{}", err))),
+    };
+
+    let stdout = wait_output(stdout);
+    let stderr = wait_output(stderr);
+
+    if status.success() {
+        Ok(TaskOutput {
+            stdout: stdout,
+            stderr: stderr,
+        })
+    } else {
+        Err(TaskError::Command(status, stdout, stderr))
+    }
+}
+
+// Reads everything from a pipe on a new thread, keeping at most `limit`
+// bytes of it, and sends it once the pipe is closed.
+fn read_limited<R>(mut reader: R, limit: Option<usize>) -> Receiver<String>
+    where R: Read + Send + 'static {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut output = vec![];
+        let mut chunk = [0; 8192];
+        let mut truncated = false;
+
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+
+            let keep = match limit {
+                Some(limit) => cmp::min(read, limit.saturating_sub(output.len())),
+                None => read,
+            };
+
+            truncated = truncated || keep < read;
+            output.extend_from_slice(&chunk[..keep]);
+        }
+
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        if truncated {
+            output.push_str("\n[output truncated]");
+        }
+        let _ = sender.send(output);
+    });
+
+    receiver
+}
+
+// Waits a bounded time for the output read by `read_limited`.
+fn wait_output(receiver: Receiver<String>) -> String {
+    receiver.recv_timeout(Duration::from_secs(OUTPUT_WAIT))
+            .unwrap_or_else(|_| "[output incomplete: the build left processes running]".to_owned())
+}
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Key identifying a crate: just its name in the default registry, otherwise
+/// prefixed with the registry's name (eg. "my-registry/foo").
+pub fn crate_key(registry: &str, name: &str) -> String {
+    if registry == registry::DEFAULT {
+        name.to_owned()
+    } else {
+        format!("{}/{}", registry, name)
+    }
+}
+
+/// Key identifying a crate version (eg. "foo-1.2.3")
+pub fn crate_pair(registry: &str, name: &str, version: &str) -> String {
+    format!("{}-{}", crate_key(registry, name), version)
+}
+
+/// Compares version numbers such as "1.2.3" and "1.3.0-beta.1" part by part,
+/// ordering pre-releases before their release. Parts that aren't numbers are
+/// compared as strings.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let split = |version: &str| -> (Vec<String>, Option<String>) {
+        let mut parts = version.splitn(2, '-');
+        let release = parts.next().unwrap().split('.').map(|part| part.to_owned()).collect();
+        (release, parts.next().map(|pre| pre.to_owned()))
+    };
+    let (a_release, a_pre) = split(a);
+    let (b_release, b_pre) = split(b);
+
+    for (a_part, b_part) in a_release.iter().zip(b_release.iter()) {
+        let ordering = match (a_part.parse::<u64>(), b_part.parse::<u64>()) {
+            (Ok(a_part), Ok(b_part)) => a_part.cmp(&b_part),
+            _ => a_part.cmp(b_part),
+        };
+        if ordering != Ordering::Equal {
+            return ordering
+        }
+    }
+
+    match a_release.len().cmp(&b_release.len()) {
+        Ordering::Equal => {
+            match (a_pre, b_pre) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a_pre), Some(b_pre)) => a_pre.cmp(&b_pre),
+            }
+        },
+        ordering => ordering,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::time::Instant;
+
+    use super::run_limited;
+    use super::super::tasks::TaskError;
+
+    #[test]
+    fn timeout_kills_process_group() {
+        // The background sleep keeps the pipes open unless it's killed too
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("sleep 30 & sleep 30");
+
+        let started = Instant::now();
+        match run_limited(command, Some(1), None, || {}) {
+            Err(TaskError::Timeout(1)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(started.elapsed().as_secs() < 10);
+    }
+
+    #[test]
+    fn limits_output() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo 0123456789");
+
+        let output = run_limited(command, None, Some(4), || {}).unwrap();
+        assert_eq!(output.stdout, "0123\n[output truncated]");
+    }
+}
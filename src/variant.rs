@@ -0,0 +1,224 @@
+use std::str;
+
+use super::package::DocsMetadata;
+
+/// Separates a crate version from its variant's key in store directories and
+/// URLs (eg. "1.2.3~private-items"). Versions can't contain it.
+pub const SEPARATOR: char = '~';
+
+/// A way of building a crate version's docs other than the default one the
+/// crate's docs.rs metadata asks for. Each variant is built on demand and
+/// stored separately.
+#[derive(Clone, Debug, Default, Eq, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Variant {
+    /// Features to enable instead of those in the docs.rs metadata
+    pub features: Vec<String>,
+    pub no_default_features: bool,
+    /// Whether to document private items too
+    pub private_items: bool,
+}
+
+impl Variant {
+    /// Builds a variant from query parameters (eg.
+    /// `?features=serde,std&no-default-features=true&private-items=true`),
+    /// ignoring any others. Returns `None` if they don't ask for anything but
+    /// the default.
+    pub fn from_query(query: Option<&str>) -> Result<Option<Variant>, String> {
+        let mut variant = Variant::default();
+
+        for param in query.unwrap_or("").split('&').filter(|param| !param.is_empty()) {
+            let mut parts = param.splitn(2, '=');
+            let key = parts.next().unwrap();
+            let value = &try!(percent_decode(parts.next().unwrap_or("true")));
+
+            match key {
+                "features" => {
+                    variant.features = value.split(|c: char| c == ',' || c == '+' || c == ' ')
+                                            .filter(|feature| !feature.is_empty())
+                                            .map(|feature| feature.to_owned())
+                                            .collect();
+                },
+                "no-default-features" => variant.no_default_features = value != "false",
+                "private-items" => variant.private_items = value != "false",
+                _ => (),
+            }
+        }
+
+        variant.normalize()
+    }
+
+    /// Parses a key made by `Variant::key`.
+    pub fn from_key(key: &str) -> Option<Variant> {
+        let mut variant = Variant::default();
+
+        for part in key.split(',') {
+            if part.starts_with("features=") {
+                variant.features = part["features=".len()..].split('+').map(|feature| feature.replace(':', "/")).collect();
+            } else if part == "no-default-features" {
+                variant.no_default_features = true;
+            } else if part == "private-items" {
+                variant.private_items = true;
+            } else {
+                return None
+            }
+        }
+
+        variant.normalize().ok().and_then(|variant| variant)
+    }
+
+    /// Identifies the variant in store paths, URLs and Redis keys (eg.
+    /// "features=serde+std+dep:feature,no-default-features"). Features of
+    /// dependencies are written with a colon rather than a slash, since the
+    /// key is part of a path.
+    pub fn key(&self) -> String {
+        let mut parts = vec![];
+
+        if !self.features.is_empty() {
+            let features: Vec<String> = self.features.iter().map(|feature| feature.replace('/', ":")).collect();
+            parts.push(format!("features={}", features.join("+")));
+        }
+        if self.no_default_features {
+            parts.push("no-default-features".to_owned());
+        }
+        if self.private_items {
+            parts.push("private-items".to_owned());
+        }
+
+        parts.join(",")
+    }
+
+    /// Query string that requests this variant
+    pub fn query(&self) -> String {
+        let mut params = vec![];
+
+        if !self.features.is_empty() {
+            params.push(format!("features={}", self.features.join(",")));
+        }
+        if self.no_default_features {
+            params.push("no-default-features=true".to_owned());
+        }
+        if self.private_items {
+            params.push("private-items=true".to_owned());
+        }
+
+        params.join("&")
+    }
+
+    /// Returns the crate's docs.rs metadata changed to build this variant.
+    pub fn apply(&self, metadata: &DocsMetadata) -> DocsMetadata {
+        let mut metadata = metadata.clone();
+
+        if !self.features.is_empty() {
+            metadata.features = self.features.clone();
+            metadata.all_features = false;
+        }
+        metadata.no_default_features = self.no_default_features;
+        metadata.document_private_items = self.private_items;
+
+        metadata
+    }
+
+    // Sorts the features so that equivalent variants have the same key, and
+    // checks that they can safely be used in paths. A feature may be one of a
+    // dependency's (eg. "serde/std").
+    fn normalize(mut self) -> Result<Option<Variant>, String> {
+        let valid = |name: &str| {
+            !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        };
+
+        for feature in &self.features {
+            let mut parts = feature.splitn(2, '/');
+            let first = parts.next().unwrap();
+
+            if !valid(first) || !parts.next().map_or(true, valid) {
+                return Err(format!("Invalid feature name {:?}", feature))
+            }
+        }
+
+        self.features.sort();
+        self.features.dedup();
+
+        if self == Variant::default() {
+            Ok(None)
+        } else {
+            Ok(Some(self))
+        }
+    }
+}
+
+// Decodes a query parameter's value, in which spaces may be sent as "+".
+fn percent_decode(value: &str) -> Result<String, String> {
+    let invalid = || format!("Invalid query parameter value {:?}", value);
+    let bytes = value.as_bytes();
+    let mut decoded = vec![];
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 3 <= bytes.len() => {
+                let hex = try!(str::from_utf8(&bytes[i + 1..i + 3]).map_err(|_| invalid()));
+                decoded.push(try!(u8::from_str_radix(hex, 16).map_err(|_| invalid())));
+                i += 3;
+            },
+            b'%' => return Err(invalid()),
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            },
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+/// Splits a version from a store directory or URL into the version and its
+/// variant, if any (eg. "1.2.3~private-items").
+pub fn split_version(version: &str) -> Option<(&str, Option<Variant>)> {
+    let mut parts = version.splitn(2, SEPARATOR);
+    let version = parts.next().unwrap();
+
+    match parts.next() {
+        Some(key) => Variant::from_key(key).map(|variant| (version, Some(variant))),
+        None => Some((version, None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Variant;
+
+    fn features(features: &[&str]) -> Option<Variant> {
+        Some(Variant {
+            features: features.iter().map(|feature| feature.to_string()).collect(),
+            ..Variant::default()
+        })
+    }
+
+    #[test]
+    fn ignores_unknown_parameters() {
+        assert_eq!(Variant::from_query(Some("search=foo")), Ok(None));
+        assert_eq!(Variant::from_query(Some("search=foo&features=std")), Ok(features(&["std"])));
+    }
+
+    #[test]
+    fn decodes_features() {
+        assert_eq!(Variant::from_query(Some("features=std%2Cserde")), Ok(features(&["serde", "std"])));
+        assert_eq!(Variant::from_query(Some("features=std+serde")), Ok(features(&["serde", "std"])));
+        assert_eq!(Variant::from_query(Some("features=serde%2Fstd")), Ok(features(&["serde/std"])));
+        assert!(Variant::from_query(Some("features=%2F")).is_err());
+        assert!(Variant::from_query(Some("features=..%2F..")).is_err());
+        assert!(Variant::from_query(Some("features=%2")).is_err());
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        let variant = features(&["serde/std", "std"]).unwrap();
+
+        assert_eq!(variant.key(), "features=serde:std+std");
+        assert_eq!(Variant::from_key(&variant.key()), Some(variant));
+    }
+}
@@ -3,23 +3,23 @@ use iron::status;
 use rustc_serialize::json::{self, Json, ToJson};
 use std::collections::BTreeMap;
 
-use super::super::cratesio::Client;
 use super::super::db::GetDb;
-use super::super::store::GetStore;
+use super::super::registry::GetRegistries;
+use super::super::store::{GetStore, JSON_FILE};
 use super::super::web::GetRouter;
-use super::util::get_name_and_version;
+use super::util::{self, get_registry_name};
 
 pub fn get_crate(request: &mut Request) -> IronResult<Response> {
-    let ref name = request.get_router().find("name").unwrap();
+    let name = request.get_router().find("name").unwrap();
+    let registry_name = get_registry_name(request);
 
-    let metadata = {
-        let db = request.get_db().lock().unwrap();
-
-        db.get_crate(name.clone(), || {
-            Client::new().get_crate(name)
-        }, Some(300))
+    let registry = match request.get_registries().get(registry_name) {
+        Some(registry) => registry,
+        None => return Ok(Response::with((status::NotFound))),
     };
 
+    let metadata = util::get_crate(request.get_db(), registry_name, registry, name);
+
     match metadata {
         Ok(metadata) => {
             Ok(Response::with((
@@ -34,41 +34,130 @@ pub fn get_crate(request: &mut Request) -> IronResult<Response> {
 }
 
 pub fn get_crate_status(request: &mut Request) -> IronResult<Response> {
-    let (name, version) = get_name_and_version(request);
+    let job = match util::get_variant_job(request) {
+        Ok(job) => job,
+        Err(err) => return Ok(Response::with((status::BadRequest, err))),
+    };
     let db = request.get_db().clone();
     let store = request.get_store();
 
-    let krate = store.make_crate(name, version);
+    let krate = store.for_job(&job);
 
     let downloaded = store.contains(&krate);
-    let (downloading, failed) = {
-        let pair = format!("{}-{}", name, version);
+    let (downloading, failed, queued, attempts) = {
+        let pair = job.pair();
         let db = db.lock().unwrap();
-        (db.is_build_in_progress(&krate), db.get_failed(&pair).is_some())
+        let queued = match db.is_queued(&pair) {
+            Ok(queued) => queued,
+            Err(err) => return Ok(Response::with((status::InternalServerError, err.0))),
+        };
+
+        (db.is_build_in_progress(&krate), db.get_failed(&pair), queued, db.get_attempts(&pair))
     };
 
-    let status = match (downloaded, downloading, failed) {
+    let status = match (downloaded, downloading, failed.is_some()) {
         // Failed to download
         (false, false, true) => "failed",
+        // Waiting in the build queue
+        (false, false, false) if queued => "queued",
         // Not downloaded or downloading
         (false, false, false) => "missing",
         // Already downloading/building
         (false, true, false) => "downloading",
-        // Downloaded
-        (true, false, false) => "downloaded",
+        // Downloaded, or published by a build that's just finishing
+        (true, _, false) => "downloaded",
         _ => {
             panic!("Unreachable state: downloaded = {:?}, downloading = {:?}, failed = {:?}",
                    downloaded,
                    downloading,
-                   failed)
+                   failed.is_some())
         }
     };
 
     let mut body: BTreeMap<String, Json> = BTreeMap::new();
     body.insert("status".to_owned(), status.to_json());
+    match job.variant {
+        Some(ref variant) => {
+            body.insert("variant".to_owned(), variant.key().to_json());
+        },
+        None => {
+            // Variants of the version that have been built
+            let variants = store.for_registry(&job.registry).crate_variants(&job.name, &job.version);
+            let keys: Vec<String> = variants.iter().map(|variant| variant.key()).collect();
+            body.insert("variants".to_owned(), keys.to_json());
+        },
+    }
+    if let Some(manifest) = krate.manifest() {
+        if let Some(checksum) = manifest.checksum {
+            body.insert("checksum".to_owned(), checksum.to_json());
+        }
+        if let Some(toolchain) = manifest.toolchain {
+            body.insert("toolchain".to_owned(), toolchain.to_json());
+        }
+        if let Some(rustc) = manifest.rustc {
+            body.insert("rustc".to_owned(), rustc.to_json());
+        }
+        if let Some(rustdoc_json) = manifest.rustdoc_json {
+            body.insert("rustdoc_json".to_owned(), rustdoc_json.to_json());
+        }
+        if let Some(image) = manifest.image {
+            body.insert("image".to_owned(), image.to_json());
+        }
+        if let Some(fallback) = manifest.fallback {
+            body.insert("fallback".to_owned(), fallback.to_json());
+        }
+    }
+    if let Some(attempts) = attempts {
+        body.insert("attempts".to_owned(), attempts.to_json());
+    }
+    if let Some(failed) = failed {
+        let failure = btreemap!{
+            "code".to_owned()     => failed.code.to_json(),
+            "message".to_owned()  => failed.message.to_json(),
+            "stage".to_owned()    => failed.stage.to_json(),
+            "category".to_owned() => failed.category.to_json(),
+            "attempts".to_owned() => failed.attempts.to_json(),
+        };
+        body.insert("failure".to_owned(), Json::Object(failure));
+    }
 
     Ok(Response::with((
         status::Ok,
         json::encode(&body).unwrap()
     )))
 }
+
+pub fn get_crate_builds(request: &mut Request) -> IronResult<Response> {
+    let pair = match util::get_variant_job(request) {
+        Ok(job) => job.pair(),
+        Err(err) => return Ok(Response::with((status::BadRequest, err))),
+    };
+
+    let builds = {
+        let db = request.get_db().lock().unwrap();
+        db.get_builds(&pair)
+    };
+
+    match builds {
+        Ok(builds) => Ok(Response::with((status::Ok, json::encode(&builds).unwrap()))),
+        Err(err) => Ok(Response::with((status::InternalServerError, format!("Unable to get the builds of {}: {}", pair, err.0)))),
+    }
+}
+
+pub fn get_rustdoc_json(request: &mut Request) -> IronResult<Response> {
+    let job = match util::get_variant_job(request) {
+        Ok(job) => job,
+        Err(err) => return Ok(Response::with((status::BadRequest, err))),
+    };
+    let krate = request.get_store().for_job(&job);
+
+    if !request.get_store().contains(&krate) {
+        return Ok(Response::with((status::NotFound)))
+    }
+    { request.get_db().lock().unwrap().record_access(&job.pair()) };
+
+    match krate.open_file(JSON_FILE) {
+        Some(reader) => Ok(Response::with((status::Ok, util::content_type(JSON_FILE), reader))),
+        None => Ok(Response::with((status::NotFound))),
+    }
+}
@@ -2,38 +2,94 @@ use handlebars_iron::Template;
 use iron::prelude::*;
 use iron::modifiers::Redirect;
 use iron::status;
-use rustc_serialize::json::Json;
-use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use rustc_serialize::json::{self, Json};
 
-use super::super::builder::Builder;
 use super::super::db::GetDb;
-use super::super::store::GetStore;
+use super::super::queue::Job;
+use super::super::registry::{self, GetRegistries};
+use super::super::store::{GetStore, StoredCrate};
+use super::super::variant;
 use super::super::web::GetRouter;
-use super::util::{self, get_name_and_version};
+use super::util::{self, base_path, get_registry_name};
 
 pub fn get_index(request: &mut Request) -> IronResult<Response> {
-    let store = request.get_store();
+    let registry_name = get_registry_name(request);
+
+    if request.get_registries().get(registry_name).is_none() {
+        return Ok(Response::with((status::NotFound)))
+    }
+
+    let store = request.get_store().for_registry(registry_name);
     let crate_names = store.crate_names();
 
+    // Other registries that docs are served for
+    let registries = request.get_registries().names().into_iter()
+        .filter(|name| name != registry_name)
+        .map(|name| {
+            Json::Object(btreemap!{
+                "path".to_owned() => Json::String(format!("{}/", base_path(&name))),
+                "name".to_owned() => Json::String(name),
+            })
+        }).collect();
+
+    let title = if registry_name == registry::DEFAULT {
+        "Rust Crates documentation".to_owned()
+    } else {
+        format!("Rust Crates documentation for {}", registry_name)
+    };
+
     Ok(Response::with((
         status::Ok,
         Template::new("index", hashmap!{
-            "title".to_owned() => Json::String("Rust Crates documentation".to_owned()),
+            "title".to_owned() => Json::String(title),
+            "base".to_owned() => Json::String(base_path(registry_name)),
             "crate_names".to_owned() => Json::Array(crate_names.into_iter().map(|n| Json::String(n)).collect()),
+            "registries".to_owned() => Json::Array(registries),
         })
     )))
 }
 
 pub fn get_crate_index(request: &mut Request) -> IronResult<Response> {
-    let store = request.get_store();
+    let registry_name = get_registry_name(request);
+    let store = request.get_store().for_registry(registry_name);
     let name = request.get_router().find("name").unwrap();
+    let base = base_path(registry_name);
 
     if let Some(versions) = store.crate_versions(name) {
         let versions = versions.into_iter().map(|version| {
+            let manifest = store.make_crate(name, &version).manifest().unwrap_or_default();
+            let path = format!("{}/crates/{}/{}", base, name, version);
+            // Prefer the exact compiler version over the toolchain's name
+            let toolchain = manifest.rustc.or(manifest.toolchain);
+
+            // Links to the docs of each target, starting with the default
+            let mut targets = vec![];
+            if let Some(ref other_targets) = manifest.targets {
+                if !other_targets.is_empty() {
+                    let default_target = manifest.default_target.clone().unwrap_or("default".to_owned());
+                    targets.push(target_link(default_target, path.clone()));
+                }
+
+                for target in other_targets {
+                    targets.push(target_link(target.clone(), format!("{}/{}", path, target)));
+                }
+            }
+
+            // Links to the variants that have been built
+            let variants = store.crate_variants(name, &version).into_iter().map(|variant| {
+                Json::Object(btreemap!{
+                    "variant".to_owned() => Json::String(variant.key()),
+                    "path".to_owned() =>    Json::String(format!("{}?{}", path, variant.query())),
+                })
+            }).collect();
+
             Json::Object(btreemap!{
-                "path".to_owned() =>    Json::String(format!("/crates/{}/{}", name, version)),
-                "version".to_owned() => Json::String(version),
+                "path".to_owned() =>      Json::String(path),
+                "version".to_owned() =>   Json::String(version),
+                "toolchain".to_owned() => toolchain.map(Json::String).unwrap_or(Json::Null),
+                "fallback".to_owned() =>  manifest.fallback.map(Json::String).unwrap_or(Json::Null),
+                "targets".to_owned() =>   Json::Array(targets),
+                "variants".to_owned() =>  Json::Array(variants),
             })
         }).collect();
 
@@ -41,6 +97,7 @@ pub fn get_crate_index(request: &mut Request) -> IronResult<Response> {
             status::Ok,
             Template::new("crate_index", hashmap!{
                 "title".to_owned()    => Json::String(format!("{} versions", name)),
+                "base".to_owned()     => Json::String(base.clone()),
                 "name".to_owned()     => Json::String(name.to_owned()),
                 "versions".to_owned() => Json::Array(versions),
             })
@@ -51,93 +108,183 @@ pub fn get_crate_index(request: &mut Request) -> IronResult<Response> {
 }
 
 pub fn get_docs(request: &mut Request) -> IronResult<Response> {
-    let (name, version) = get_name_and_version(request);
+    let job = match util::get_variant_job(request) {
+        Ok(job) => job,
+        Err(err) => return Ok(Response::with((status::BadRequest, err))),
+    };
+
+    if let Some(response) = queue_if_missing(request, &job) {
+        return Ok(response)
+    }
+
+    // Downloaded, or published by a build that's just finishing
+    let registry_name = get_registry_name(request);
+    let krate = request.get_store().for_job(&job);
+    { request.get_db().lock().unwrap().record_access(&job.pair()) };
+
+    // Crates without an obvious page get a list of their docs
+    let entry_point = match krate.entry_point(&job.name) {
+        Some(entry_point) => entry_point,
+        None => return Ok(docs_landing_page(registry_name, &job, &krate)),
+    };
+
+    let mut url = request.url.clone();
+    if url.path.last().unwrap() == "" {
+        url.path.pop();
+    }
+
+    // Variants are served from their own directory
+    if let Some(ref variant) = job.variant {
+        *url.path.last_mut().unwrap() = format!("{}{}{}", job.version, variant::SEPARATOR, variant.key());
+        url.query = None;
+    }
+
+    url.path.push(entry_point);
+    url.path.push("index.html".to_owned());
+
+    Ok(Response::with((status::Found, Redirect(url))))
+}
+
+// Queues a crate version's docs to be built unless they're stored or being
+// built already. Returns the response to give instead of the docs (eg. that
+// they're being built, or that the version doesn't exist), or `None` if
+// they're stored.
+fn queue_if_missing(request: &Request, job: &Job) -> Option<Response> {
+    let registry_name = get_registry_name(request);
     let db = request.get_db().clone();
     let store = request.get_store();
 
-    let metadata = util::get_crate(request.get_db(), &name);
+    let metadata = match request.get_registries().get(registry_name) {
+        Some(registry) => util::get_crate(request.get_db(), registry_name, registry, &job.name),
+        None => return Some(Response::with((status::NotFound))),
+    };
 
     if let Err(_) = metadata {
-        return Ok(Response::with((status::NotFound)))
+        return Some(Response::with((status::NotFound)))
     }
 
-    if !metadata.unwrap().versions.contains(&version.to_owned()) {
-        return Ok(Response::with((status::NotFound)))
+    if !metadata.unwrap().versions.contains(&job.version) {
+        return Some(Response::with((status::NotFound)))
     }
 
-    let pair = format!("{}-{}", name.clone(), version.clone());
+    let pair = job.pair();
     let failed = { db.lock().unwrap().get_failed(&pair) };
     if let Some(failed) = failed {
-        return Ok(Response::with((
+        return Some(Response::with((
             status::Ok,
-            format!("Unable to build {}:\n\n{}", pair, failed.message)
+            format!("Unable to build {} ({} failed in the {} stage):\n\n{}", pair, failed.category, failed.stage, failed.message)
         )))
     }
 
-    let krate = store.make_crate(&name, &version);
+    let krate = store.for_job(job);
 
     let downloaded  = store.contains(&krate);
     let downloading = { db.lock().unwrap().is_build_in_progress(&krate) };
 
     match (downloaded, downloading) {
-        // Not downloaded or downloading, so start a new download and build
+        // Not downloaded or downloading, so queue up a new download and build
         (false, false) => {
-            let builder = Builder::new(&name, &version, krate.clone());
+            let queued = { db.lock().unwrap().enqueue_build(job) };
 
-            Builder::spawn(db, Arc::new(RwLock::new(builder)));
-
-            Ok(Response::with((
-                status::Ok,
-                format!("Building {} version {}...", name, version)
-            )))
+            match queued {
+                Ok(_) => Some(Response::with((status::Ok, format!("Queued {} for building...", pair)))),
+                Err(err) => {
+                    Some(Response::with((status::InternalServerError, format!("Unable to queue {} for building: {}", pair, err.0))))
+                },
+            }
         },
         // Already downloading/building
         (false, true) => {
-            Ok(Response::with((
+            Some(Response::with((
                 status::Ok,
-                format!("Already building {} version {}...", name, version)
+                format!("Already building {}...", pair)
             )))
         },
-        // Downloaded
-        (true, false) => {
-            let mut url = request.url.clone();
-            url.path.push(name.to_owned());
-            url.path.push("index.html".to_owned());
+        (true, _) => None,
+    }
+}
+
+pub fn get_builds(request: &mut Request) -> IronResult<Response> {
+    let job = match util::get_job(request) {
+        Ok(job) => job,
+        Err(err) => return Ok(Response::with((status::BadRequest, err))),
+    };
+    let (name, version) = (&job.name, &job.version);
+    let registry_name = get_registry_name(request);
+    let pair = job.pair();
 
-            return Ok(Response::with((status::Found, Redirect(url))))
+    let builds = {
+        let db = request.get_db().lock().unwrap();
+        db.get_builds(&pair)
+    };
+    let builds = match builds {
+        Ok(builds) => builds,
+        Err(err) => {
+            return Ok(Response::with((status::InternalServerError, format!("Unable to get the builds of {}: {}", pair, err.0))))
         },
-        _ => {
-            panic!("Unreachable state: downloaded = {:?}, downloading = {:?}",
-                   downloaded,
-                   downloading)
-        }
-    }
+    };
+
+    Ok(Response::with((
+        status::Ok,
+        Template::new("builds", hashmap!{
+            "title".to_owned()   => Json::String(format!("{} {} builds", name, version)),
+            "base".to_owned()    => Json::String(base_path(registry_name)),
+            "name".to_owned()    => Json::String(name.to_owned()),
+            "version".to_owned() => Json::String(version.to_owned()),
+            "builds".to_owned()  => Json::from_str(&json::encode(&builds).unwrap()).unwrap(),
+        })
+    )))
 }
 
 pub fn get_doc_file(request: &mut Request) -> IronResult<Response> {
-    let (name, version) = get_name_and_version(request);
+    let job = match util::get_job(request) {
+        Ok(job) => job,
+        Err(err) => return Ok(Response::with((status::BadRequest, err))),
+    };
     let ref requested_path = sanitize_requested_path(request.get_router().find("path").unwrap());
-    let store = request.get_store();
-
-    let krate = store.make_crate(&name, &version);
+    let krate = request.get_store().for_job(&job);
 
-    if !store.contains(&krate) {
-        return Ok(Response::with((status::NotFound)))
+    // The docs may have been evicted to make room for others, so queue them
+    // to be built again, as though the crate version had been requested
+    if !request.get_store().contains(&krate) {
+        if let Some(response) = queue_if_missing(request, &job) {
+            return Ok(response)
+        }
     }
 
-    let mut path_buf = PathBuf::from(krate.0);
-    path_buf.extend(Path::new(requested_path));
+    { request.get_db().lock().unwrap().record_access(&job.pair()) };
+
+    // Docs for other targets live in subdirectories named after them, so
+    // send requests for a target to its crate's docs
+    let targets = krate.manifest().and_then(|manifest| manifest.targets).unwrap_or(vec![]);
+    if targets.contains(requested_path) {
+        let entry_point = match krate.entry_point(&job.name) {
+            Some(entry_point) => entry_point,
+            None => return Ok(docs_landing_page(get_registry_name(request), &job, &krate)),
+        };
+
+        let mut url = request.url.clone();
+        if url.path.last().unwrap() == "" {
+            url.path.pop();
+        }
+        url.path.push(entry_point);
+        url.path.push("index.html".to_owned());
+
+        return Ok(Response::with((status::Found, Redirect(url))))
+    }
 
-    if path_buf.is_file() {
+    // Stream the file rather than reading it into memory, since the store
+    // may be remote
+    if let Some(reader) = krate.open_file(requested_path) {
         return Ok(Response::with((
             status::Ok,
-            path_buf.as_path()
+            util::content_type(requested_path),
+            reader
         )))
     }
 
     // Check if we can serve an "index.html"
-    let index_path_buf = path_buf.join(Path::new("index.html"));
-    if index_path_buf.is_file() {
+    if krate.has_file(&format!("{}/index.html", requested_path)) {
         let mut index_url = request.url.clone();
         // Remove a trailing slash if found
         if index_url.path.last().unwrap() == "" {
@@ -157,6 +304,58 @@ pub fn get_doc_file(request: &mut Request) -> IronResult<Response> {
     )))
 }
 
+// Lists the docs of each crate documented by a build (eg. each binary of a
+// crate without a library) for each of its targets.
+fn docs_landing_page(registry_name: &str, job: &Job, krate: &StoredCrate) -> Response {
+    let base = base_path(registry_name);
+    let version = match job.variant {
+        Some(ref variant) => format!("{}{}{}", job.version, variant::SEPARATOR, variant.key()),
+        None => job.version.clone(),
+    };
+    let path = format!("{}/crates/{}/{}", base, job.name, version);
+
+    let manifest = krate.manifest().unwrap_or_default();
+    let entry_points = krate.entry_points(&job.name);
+
+    let mut targets = vec![(manifest.default_target.clone().unwrap_or("default".to_owned()), path.clone())];
+    for target in manifest.targets.unwrap_or(vec![]) {
+        let target_path = format!("{}/{}", path, target);
+        targets.push((target, target_path));
+    }
+
+    let targets = targets.into_iter().map(|(target, target_path)| {
+        let crates = entry_points.iter().map(|entry_point| {
+            Json::Object(btreemap!{
+                "name".to_owned() => Json::String(entry_point.clone()),
+                "path".to_owned() => Json::String(format!("{}/{}/index.html", target_path, entry_point)),
+            })
+        }).collect();
+
+        Json::Object(btreemap!{
+            "target".to_owned() => Json::String(target),
+            "crates".to_owned() => Json::Array(crates),
+        })
+    }).collect();
+
+    Response::with((
+        status::Ok,
+        Template::new("docs_index", hashmap!{
+            "title".to_owned()   => Json::String(format!("{} {} documentation", job.name, job.version)),
+            "base".to_owned()    => Json::String(base),
+            "name".to_owned()    => Json::String(job.name.clone()),
+            "version".to_owned() => Json::String(job.version.clone()),
+            "targets".to_owned() => Json::Array(targets),
+        })
+    ))
+}
+
+fn target_link(target: String, path: String) -> Json {
+    Json::Object(btreemap!{
+        "target".to_owned() => Json::String(target),
+        "path".to_owned() =>   Json::String(path),
+    })
+}
+
 fn sanitize_requested_path(path: &str) -> String {
     let mut path = path;
 
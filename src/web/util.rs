@@ -1,11 +1,16 @@
+use hyper::mime::Mime;
 use iron::prelude::Request;
 use plugin::Extensible;
 use router::Router;
 use route_recognizer::Params;
 use std::sync::{Arc, Mutex};
 
-use super::super::cratesio::{Client, Error, Metadata};
+use super::super::cratesio::{Error, Metadata};
 use super::super::db::Db;
+use super::super::queue::Job;
+use super::super::registry::{self, Registry};
+use super::super::util::crate_key;
+use super::super::variant::{self, Variant};
 
 pub trait GetRouter {
     fn get_router(&self) -> &Params;
@@ -17,12 +22,48 @@ impl<'a, 'b> GetRouter for Request<'a, 'b> {
     }
 }
 
-pub fn get_crate(db: &Arc<Mutex<Db>>, name: &str) -> Result<Metadata, Error> {
+pub fn get_crate(db: &Arc<Mutex<Db>>, registry_name: &str, registry: &Registry, name: &str) -> Result<Metadata, Error> {
     let db = db.lock().unwrap();
 
-    db.get_crate(name.clone(), || {
-        Client::new().get_crate(name)
-    }, Some(300))
+    db.get_crate(&crate_key(registry_name, name), || {
+        registry.get_crate(name)
+    }, Some(db.metadata_ttl))
+}
+
+/// Name of the registry given in the URL, or the default registry.
+pub fn get_registry_name<'a>(request: &'a Request) -> &'a str {
+    request.get_router().find("registry").unwrap_or(registry::DEFAULT)
+}
+
+/// Prefix for links to pages of crates in a registry
+pub fn base_path(registry_name: &str) -> String {
+    if registry_name == registry::DEFAULT {
+        "".to_owned()
+    } else {
+        format!("/registries/{}", registry_name)
+    }
+}
+
+/// Content type of a file served from stored docs, by its extension
+pub fn content_type(path: &str) -> Mime {
+    let extension = path.rsplit('.').next().unwrap_or("");
+
+    let content_type = match extension {
+        "html" => "text/html; charset=utf-8",
+        "css"  => "text/css; charset=utf-8",
+        "js"   => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "svg"  => "image/svg+xml",
+        "png"  => "image/png",
+        "ico"  => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf"  => "font/ttf",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    };
+
+    content_type.parse().unwrap()
 }
 
 pub fn get_name_and_version<'a>(request: &'a Request) -> (&'a str, &'a str) {
@@ -31,3 +72,28 @@ pub fn get_name_and_version<'a>(request: &'a Request) -> (&'a str, &'a str) {
 
     (name, version)
 }
+
+/// Builds a job for the crate version in the URL, whose variant, if any, is
+/// given in the version (eg. "1.2.3~private-items").
+pub fn get_job(request: &Request) -> Result<Job, String> {
+    let (name, version) = get_name_and_version(request);
+
+    match variant::split_version(version) {
+        Some((version, variant)) => Ok(Job::with_variant(get_registry_name(request), name, version, variant)),
+        None => Err(format!("Invalid variant in {:?}", version)),
+    }
+}
+
+/// Like `get_job`, but the variant may also be given as query parameters
+/// (see `Variant::from_query`). Only for pages that request a variant, since
+/// rustdoc's pages have query parameters of their own.
+pub fn get_variant_job(request: &Request) -> Result<Job, String> {
+    let job = try!(get_job(request));
+    if job.variant.is_some() {
+        return Ok(job)
+    }
+
+    let variant = try!(Variant::from_query(request.url.query.as_ref().map(|query| &query[..])));
+
+    Ok(Job::with_variant(&job.registry, &job.name, &job.version, variant))
+}